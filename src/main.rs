@@ -2,7 +2,9 @@ mod shell;
 use shell::Shell;
 
 fn main() {
-    println!("Hello, world!");
     let mut interactive_shell = Shell::new();
+    if let Some(path) = std::env::args().nth(1) {
+        std::process::exit(interactive_shell.run_file(&path));
+    }
     interactive_shell.start();
 }
@@ -1,31 +1,71 @@
-use ast::Ast;
 use environment::ShellEnv;
-use lexer::Lexer;
+use exec::Io;
+use interpreter::{EvalResult, ShellError, Span, Unwind};
+use lexer::{Lexer, Token, ends_here_doc, here_doc_start};
 use parser::Parser;
-use std::io::{self, Write};
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::fd::OwnedFd;
+use values::Value;
 
 mod ast;
+mod builtins;
+mod editor;
 mod environment;
+mod exec;
+mod functions;
+mod interpreter;
+mod jobs;
 mod lexer;
 mod parser;
+mod patterns;
+mod signals;
 mod values;
 
 pub struct Shell {
     env: ShellEnv,
-    parser: Parser,
     lexer: Lexer,
+    alias_tokens: HashMap<String, Vec<Token>>, // each alias's value, lexed when it's defined
+    io: Io,
+    conditions: usize, // depth of `if`/`while` conditions and `&&`/`||` left sides
+    regexes: HashMap<String, Regex>, // compiled patterns from `=~` and the regex builtins
+    trying: usize,     // depth of `try` bodies, where failing commands throw
+    script: String,    // the file being run, for error locations
+    line: usize,       // the statement being run, for error locations and call sites
+    job_control: bool, // interactive on a terminal: jobs get process groups and Ctrl-Z works
+    shell_pgid: i32,   // the shell's own process group, which gets the terminal back
+    substitutions: Vec<(i32, OwnedFd)>, // `<(cmd)` shells of running statements, with pipes
 }
 impl Shell {
     pub fn new() -> Self {
         Shell {
             env: ShellEnv::new(),
-            parser: Parser::new(),
             lexer: Lexer::new(),
+            alias_tokens: HashMap::new(),
+            io: Io::default(),
+            conditions: 0,
+            regexes: HashMap::new(),
+            trying: 0,
+            script: "<stdin>".to_string(),
+            line: 0,
+            job_control: false,
+            shell_pgid: 0,
+            substitutions: Vec::new(),
         }
     }
     pub fn start(&mut self) {
         let mut buffer = String::new();
+        signals::catch(&[libc::SIGINT, libc::SIGQUIT]);
+        let terminal = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+        if terminal {
+            self.enable_job_control();
+        }
         loop {
+            if buffer.is_empty() {
+                self.notify_jobs();
+            }
             let prompt = if valid_delimiters(&buffer) {
                 ">>: "
             } else {
@@ -33,53 +73,179 @@ impl Shell {
             };
             print!("{prompt}");
             io::stdout().flush().unwrap();
-            let mut line = String::new();
-            if io::stdin().read_line(&mut line).is_err() {
-                println!("Error reading line");
-                break;
-            }
-            buffer.push_str(&line);
-            if valid_delimiters(&buffer) {
-                if buffer.trim() == "exit" {
+            let line = if terminal {
+                editor::read_line(&self.env.abbreviations)
+            } else {
+                read_line()
+            };
+            let line = match line {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                // Ctrl-C throws away whatever has been typed so far
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    if let Err(Unwind::Exit(code)) = self.check_signals() {
+                        std::process::exit(self.exit_traps(code));
+                    }
+                    println!();
+                    buffer.clear();
+                    continue;
+                }
+                Err(_) => {
+                    println!("Error reading line");
                     break;
                 }
+            };
+            buffer.push_str(&line);
+            if valid_delimiters(&buffer) {
                 if buffer.trim().is_empty() {
                     continue;
                 }
-                println!("{:#?}", self.parse(buffer.trim()));
+                signals::take_interrupt();
+                let result = self.run_source(buffer.trim());
+                // after Ctrl-C the cursor is still beside the terminal's `^C`
+                let interrupted =
+                    matches!(&result, Err(Unwind::Error(err)) if err.kind == "interrupt");
+                if signals::take_interrupt() || interrupted {
+                    println!();
+                }
+                match result {
+                    Ok(value) => self.emit(&value),
+                    Err(Unwind::Exit(code)) => std::process::exit(self.exit_traps(code)),
+                    Err(Unwind::Error(err)) => {
+                        self.report_error(&err);
+                        self.env.last_status = err.status;
+                    }
+                    Err(Unwind::Return(_)) => {}
+                }
                 buffer.clear();
             }
         }
+        let status = self.exit_traps(self.env.last_status);
+        std::process::exit(status);
     }
-    fn parse(&mut self, source_code: &str) -> Ast {
-        self.parser
-            .produce_ast(self.lexer.tokenize(source_code).unwrap())
+    // runs a script file and returns the status the process should exit with
+    pub fn run_file(&mut self, path: &str) -> i32 {
+        let source_code = match fs::read_to_string(path) {
+            Ok(source_code) => source_code,
+            Err(err) => {
+                self.report(&format!("{path}: {err}"));
+                return 127;
+            }
+        };
+        self.script = path.to_string();
+        signals::catch(&[libc::SIGINT, libc::SIGQUIT]);
+        let status = match self.run_source(&source_code) {
+            Ok(_) | Err(Unwind::Return(_)) => self.env.last_status,
+            Err(Unwind::Exit(code)) => code,
+            Err(Unwind::Error(err)) => {
+                self.report_error(&err);
+                err.status
+            }
+        };
+        self.exit_traps(status)
+    }
+    // input that doesn't lex or parse is reported like any other error, with the status of a
+    // usage error
+    fn syntax_error(&self, message: String, line: usize) -> Unwind {
+        Unwind::Error(ShellError {
+            message,
+            kind: "syntax".to_string(),
+            status: 2,
+            span: Some(Span {
+                file: self.script.clone(),
+                line,
+            }),
+            trace: Vec::new(),
+        })
+    }
+    // runs source code a statement at a time, parsing each once those before it have run, so
+    // that an alias defined in a script applies to its later lines as in other shells
+    fn run_source(&mut self, source_code: &str) -> EvalResult {
+        let (tokens, lines) = (self.lexer.tokenize(source_code))
+            .map_err(|err| self.syntax_error(err.message, err.line))?;
+        // a parser of its own, since a statement may `source` another file
+        let mut parser = Parser::new();
+        parser.load(tokens, lines);
+        let mut result = Value::None;
+        while let Some(statement) = (parser.next_statement(self.alias_tokens.clone()))
+            .map_err(|err| self.syntax_error(err.message, err.line))?
+        {
+            result = match self.eval_statement(&statement) {
+                Err(Unwind::Return(value)) => return Ok(value),
+                other => other?,
+            };
+        }
+        Ok(result)
     }
 }
+// a line from stdin, or `None` at its end; unlike `read_line` it gives up when a signal
+// interrupts the read, so Ctrl-C at the prompt can start over
+fn read_line() -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while io::stdin().read(&mut byte)? == 1 {
+        line.push(byte[0]);
+        if byte[0] == b'\n' {
+            break;
+        }
+    }
+    Ok((!line.is_empty()).then(|| String::from_utf8_lossy(&line).into_owned()))
+}
+// whether the buffer is complete: its strings are closed, its brackets balance and every
+// here-doc has reached its terminator; brackets and `<<` inside strings, comments and
+// here-doc bodies don't count
 fn valid_delimiters(input: &str) -> bool {
     let mut stack = Vec::new();
+    let mut here_docs = VecDeque::new();
+    let mut quoted = false;
 
-    for ch in input.chars() {
-        match ch {
-            '(' | '{' | '[' => stack.push(ch),
-            ')' => {
-                if stack.pop() != Some('(') {
-                    return false;
-                }
+    for line in input.split_inclusive('\n') {
+        if let Some(start) = here_docs.front() {
+            if ends_here_doc(line, start) {
+                here_docs.pop_front();
             }
-            '}' => {
-                if stack.pop() != Some('{') {
-                    return false;
+            continue;
+        }
+        // the line with strings blanked out and comments cut off
+        let mut code = String::new();
+        for ch in line.chars() {
+            match ch {
+                '"' => {
+                    quoted = !quoted;
+                    code.push(' ');
                 }
+                _ if quoted => {}
+                '#' => break,
+                _ => code.push(ch),
             }
-            ']' => {
-                if stack.pop() != Some('[') {
-                    return false;
-                }
+        }
+        here_docs.extend(code.split_whitespace().filter_map(here_doc_start));
+        for ch in code.chars() {
+            match ch {
+                '(' | '{' | '[' => stack.push(ch),
+                ')' if stack.pop() != Some('(') => return false,
+                '}' if stack.pop() != Some('{') => return false,
+                ']' if stack.pop() != Some('[') => return false,
+                _ => {}
             }
-            _ => {}
         }
     }
 
-    stack.is_empty()
+    !quoted && stack.is_empty() && here_docs.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::valid_delimiters;
+
+    #[test]
+    fn delimiters() {
+        assert!(valid_delimiters("x = \"a <<EOF b\"\n"));
+        assert!(valid_delimiters("echo \"(\" # {\n"));
+        assert!(!valid_delimiters("y = \"multi\n"));
+        assert!(valid_delimiters("y = \"multi\nline\"\n"));
+        assert!(!valid_delimiters("cat <<EOF\nhi \"\n"));
+        assert!(valid_delimiters("cat <<EOF\nhi \"\nEOF\n"));
+        assert!(!valid_delimiters("if x {\n"));
+    }
 }
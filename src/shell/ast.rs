@@ -1,6 +1,8 @@
 use super::values::Value;
+use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Expr {
     Literal(Value),
     Identifier(String),
@@ -9,6 +11,22 @@ pub enum Expr {
         name: String,
         value: Box<Expr>,
     },
+    IndexAssign {
+        // xs[0] = 5 / config["port"] = 80, on an index of a variable
+        target: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Destructure {
+        // [a, b, ...rest] = xs / {name, port} = config
+        pattern: Pattern,
+        value: Box<Expr>,
+    },
+    Let {
+        // let x = 1 / local x = 1
+        name: String,
+        value: Box<Expr>,
+    },
     BinaryOp {
         left: Box<Expr>,
         op: BinaryOp,
@@ -34,9 +52,20 @@ pub enum Expr {
     },
     FuncDef {
         name: String,
-        params: Vec<String>,
+        params: Vec<Pattern>,
         body: Box<Expr>,
     },
+    Lambda {
+        // function(x) { x * 2 }
+        params: Vec<Pattern>,
+        body: Rc<Expr>,
+    },
+    List(Vec<Expr>),
+    Map(Vec<(String, Expr)>),
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+    },
     AndThen {
         // cmd1 && cmd2
         left: Box<Expr>,
@@ -51,22 +80,92 @@ pub enum Expr {
         left: Box<Expr>,
         right: Box<Expr>,
     },
+    Redirect {
+        expr: Box<Expr>,
+        redirects: Vec<Redirection>,
+    },
     Return(Box<Expr>),
+    Background(Box<Expr>), // cmd &
+    Subshell(Vec<Expr>),   // ( cd build && make ), run in a copy of the shell
+    ProcessSubstitution {
+        // <(cmd) reads its output and >(cmd) writes its input, through a /dev/fd path
+        body: Vec<Expr>,
+        output: bool, // >(cmd)
+    },
     CommandCall {
         command: Box<Expr>,
         args: Vec<Expr>,
     },
+    Range {
+        // 1..10, 1..=10, 0..100 step 5
+        start: Box<Expr>,
+        end: Box<Expr>,
+        step: Option<Box<Expr>>,
+        inclusive: bool,
+    },
+    For {
+        // for x in xs { ... } / for [k, v] in pairs { ... }
+        pattern: Pattern,
+        iterable: Box<Expr>,
+        body: Box<Expr>,
+    },
+    Try {
+        // try { ... } catch e { ... } finally { ... }
+        body: Box<Expr>,
+        binding: Option<Pattern>,
+        handler: Option<Box<Expr>>,
+        finally: Option<Box<Expr>>,
+    },
+    Throw(Box<Expr>),
+    Statement {
+        // a statement in a block or script, with the line it starts on
+        line: usize,
+        expr: Box<Expr>,
+    },
+    Match {
+        // match value { pattern if guard => expr, ... }
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,                    // _
+    Binding(String),             // name, binds whatever it matches
+    Value(Box<Expr>),            // 42, "exact"
+    Glob(String),                // *.txt, unquoted
+    Regex(Box<Expr>),            // =~ "^v(\d+)", fills MATCH like the operator
+    List(Vec<Pattern>),          // [first, _, "x"], of exactly that length unless it has a rest
+    Rest(String),                // ...rest inside a list pattern, collecting what's left over
+    Map(Vec<(String, Pattern)>), // {name: n, port: 80}, other keys are ignored
+}
+
+#[derive(Debug, Clone)]
 pub enum BinaryOp {
     Add,
     Sub,
     Mul,
     Div,
     Mod,
+    Pow,
+    FloorDiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
     Eq,
     Neq,
+    Matches,
+    In,
     Lt,
     Le,
     Gt,
@@ -75,13 +174,63 @@ pub enum BinaryOp {
     Or,
 }
 
-#[derive(Debug)]
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "**",
+            BinaryOp::FloorDiv => "//",
+            BinaryOp::BitAnd => "band",
+            BinaryOp::BitOr => "bor",
+            BinaryOp::BitXor => "bxor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::Shr => "shr",
+            BinaryOp::Eq => "==",
+            BinaryOp::Neq => "!=",
+            BinaryOp::Matches => "=~",
+            BinaryOp::In => "in",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+        };
+        write!(f, "{symbol}")
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum UnaryOp {
     Neg,
     Not,
 }
 
+// one redirection of a command's descriptors; a command's redirections apply left to right,
+// so `-> log 2->&1` sends both streams to `log`
+#[derive(Debug, Clone)]
+pub struct Redirection {
+    pub fd: i32, // 0 for `<-`, 1 for `->`, or the number written before the arrow
+    pub kind: RedirectKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum RedirectKind {
+    In(Expr),           // <- path
+    Out(Expr),          // -> path
+    Append(Expr),       // >> path
+    HereString(Expr),   // <<< value
+    HereDoc(String),    // <<EOF, with its body
+    RawHereDoc(String), // <<'EOF'
+    Dup(i32),           // 2->&1, a copy of another descriptor
+    Close,              // 2->&-
+}
+
 #[derive(Debug)]
 pub struct Ast {
-    pub expr: Expr,
+    pub body: Vec<Expr>,
 }
@@ -0,0 +1,332 @@
+use super::Shell;
+use super::exec::argv;
+use super::interpreter::Unwind;
+use super::values::Value;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::Write;
+use std::mem;
+use std::path::PathBuf;
+use std::ptr;
+
+const BUILTINS: &[&str] = &[
+    "cd", "pwd", "exit", "echo", "export", "unset", "source", "set", "jobs", "fg", "bg", "wait",
+    "alias", "unalias", "abbr",
+];
+
+// a user's home directory, from the password database through the C library so NSS sources
+// like LDAP count too
+fn user_home(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut entry: libc::passwd = unsafe { mem::zeroed() };
+        let mut found = ptr::null_mut();
+        let err = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            )
+        };
+        match err {
+            // the entry's strings didn't fit, so try again with more room
+            libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+            0 if !found.is_null() && !entry.pw_dir.is_null() => {
+                let home = unsafe { CStr::from_ptr(entry.pw_dir) };
+                return Some(home.to_string_lossy().into_owned());
+            }
+            _ => return None,
+        }
+    }
+}
+
+impl Shell {
+    pub(super) fn is_builtin(name: &str) -> bool {
+        BUILTINS.contains(&name)
+    }
+    pub(super) fn run_builtin(&mut self, name: &str, args: &[Value]) -> Result<i32, Unwind> {
+        let args = argv(args);
+        match name {
+            "cd" => Ok(self.cd(&args)),
+            "pwd" => Ok(self.pwd()),
+            "exit" => self.exit(&args),
+            "echo" => Ok(self.echo(&args)),
+            "export" => Ok(self.export(&args)),
+            "unset" => {
+                args.iter().for_each(|name| self.env.unset(name));
+                Ok(0)
+            }
+            "source" => self.source(&args),
+            "set" => Ok(self.set(&args)),
+            "jobs" => Ok(self.jobs()),
+            "fg" => Ok(self.fg(&args)),
+            "bg" => Ok(self.bg(&args)),
+            "wait" => Ok(self.wait(&args)),
+            "alias" => Ok(self.alias("alias", &args)),
+            "unalias" => Ok(self.unalias("alias", &args)),
+            "abbr" if args.first().is_some_and(|flag| flag == "-e") => {
+                Ok(self.unalias("abbr", &args[1..]))
+            }
+            "abbr" => Ok(self.alias("abbr", &args)),
+            _ => unreachable!("{name} is not a builtin"),
+        }
+    }
+    // `~` and `~/x` are under HOME, `~name` under that user's home, `~+` and `~-` are PWD
+    // and OLDPWD; anything unknown is left as written
+    pub(super) fn expand_tilde(&self, word: &str) -> String {
+        let Some(rest) = word.strip_prefix('~') else {
+            return word.to_string();
+        };
+        let (user, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let home = match user {
+            "" => self.home_dir().map(|home| home.display().to_string()),
+            "+" => self.env.get("PWD").map(|dir| dir.to_string()),
+            "-" => self.env.get("OLDPWD").map(|dir| dir.to_string()),
+            _ => user_home(user),
+        };
+        match home {
+            Some(home) => format!("{home}{path}"),
+            None => word.to_string(),
+        }
+    }
+    fn home_dir(&self) -> Option<PathBuf> {
+        self.env
+            .get("HOME")
+            .map(|home| PathBuf::from(home.to_string()))
+    }
+    fn cd(&mut self, args: &[String]) -> i32 {
+        let target = match args.first().map(String::as_str) {
+            None | Some("~") => self.home_dir(),
+            Some("-") => self
+                .env
+                .get("OLDPWD")
+                .map(|dir| PathBuf::from(dir.to_string())),
+            Some(path) => match path.strip_prefix("~/") {
+                Some(rest) => self.home_dir().map(|home| home.join(rest)),
+                None => Some(PathBuf::from(path)),
+            },
+        };
+        let Some(target) = target else {
+            self.report("cd: HOME or OLDPWD not set");
+            return 1;
+        };
+        let previous = env::current_dir().ok();
+        if let Err(err) = env::set_current_dir(&target) {
+            self.report(&format!("cd: {}: {err}", target.display()));
+            return 1;
+        }
+        if args.first().is_some_and(|arg| arg == "-") {
+            writeln!(self.stdout(), "{}", target.display()).ok();
+        }
+        if let Some(previous) = previous {
+            let previous = Value::String(previous.display().to_string());
+            self.env.export("OLDPWD", Some(previous));
+        }
+        if let Ok(current) = env::current_dir() {
+            let current = Value::String(current.display().to_string());
+            self.env.export("PWD", Some(current));
+        }
+        0
+    }
+    fn pwd(&self) -> i32 {
+        match env::current_dir() {
+            Ok(dir) => {
+                writeln!(self.stdout(), "{}", dir.display()).ok();
+                0
+            }
+            Err(err) => {
+                self.report(&format!("pwd: {err}"));
+                1
+            }
+        }
+    }
+    fn exit(&self, args: &[String]) -> Result<i32, Unwind> {
+        let code = match args.first() {
+            None => self.env.last_status,
+            Some(code) => match code.parse() {
+                Ok(code) => code,
+                Err(_) => {
+                    self.report(&format!("exit: numeric argument required: {code}"));
+                    2
+                }
+            },
+        };
+        Err(Unwind::Exit(code))
+    }
+    fn echo(&self, args: &[String]) -> i32 {
+        let (newline, args) = match args.first().map(String::as_str) {
+            Some("-n") => (false, &args[1..]),
+            _ => (true, args),
+        };
+        let mut out = self.stdout();
+        write!(out, "{}", args.join(" ")).ok();
+        if newline {
+            writeln!(out).ok();
+        }
+        0
+    }
+    // `export NAME=value`, or `export NAME` to export an existing shell variable
+    fn export(&mut self, args: &[String]) -> i32 {
+        if args.is_empty() {
+            for (name, value) in self.env.exported() {
+                writeln!(self.stdout(), "{name}={value}").ok();
+            }
+            return 0;
+        }
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) => self
+                    .env
+                    .export(name, Some(Value::String(value.to_string()))),
+                None => self.env.export(arg, None),
+            }
+        }
+        0
+    }
+    // `alias` lists, `alias name` shows one, and `alias name = value` or `alias name=value`
+    // defines one; `abbr` does the same for abbreviations
+    fn alias(&mut self, kind: &str, args: &[String]) -> i32 {
+        let table = match kind {
+            "abbr" => &mut self.env.abbreviations,
+            _ => &mut self.env.aliases,
+        };
+        let show = |(name, value): (&String, &String)| format!("{kind} {name} = {value:?}");
+        let mut shown = Vec::new();
+        let mut missing = Vec::new();
+        if args.is_empty() {
+            shown.extend(table.iter().map(show));
+        }
+        let mut invalid = Vec::new();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            let definition = match args.next_if(|next| *next == "=") {
+                Some(_) => Some((arg.clone(), args.next().cloned().unwrap_or_default())),
+                None => {
+                    (arg.split_once('=')).map(|(name, value)| (name.to_string(), value.to_string()))
+                }
+            };
+            let Some((name, value)) = definition else {
+                match table.get_key_value(arg) {
+                    Some(entry) => shown.push(show(entry)),
+                    None => missing.push(arg),
+                }
+                continue;
+            };
+            // an alias is lexed once, here, so a value that doesn't lex fails to define rather
+            // than failing every line after it
+            if kind == "alias" {
+                match self.lexer.tokenize(&value) {
+                    Ok((mut tokens, _)) => {
+                        tokens.pop(); // Eof
+                        self.alias_tokens.insert(name.clone(), tokens);
+                    }
+                    Err(err) => {
+                        invalid.push(format!("{name}: {}", err.message));
+                        continue;
+                    }
+                }
+            }
+            table.insert(name, value);
+        }
+        for line in shown {
+            writeln!(self.stdout(), "{line}").ok();
+        }
+        for name in &missing {
+            self.report(&format!("{kind}: {name}: not found"));
+        }
+        for err in &invalid {
+            self.report(&format!("{kind}: {err}"));
+        }
+        i32::from(!missing.is_empty() || !invalid.is_empty())
+    }
+    // `unalias name...` or `unalias -a` for all of them; `abbr -e` likewise
+    fn unalias(&mut self, kind: &str, args: &[String]) -> i32 {
+        let table = match kind {
+            "abbr" => &mut self.env.abbreviations,
+            _ => &mut self.env.aliases,
+        };
+        let mut missing = Vec::new();
+        match args.first().is_some_and(|flag| flag == "-a") {
+            true => table.clear(),
+            false => missing.extend(args.iter().filter(|name| table.remove(*name).is_none())),
+        }
+        // the lexed aliases follow the table
+        let aliases = &self.env.aliases;
+        self.alias_tokens
+            .retain(|name, _| aliases.contains_key(name));
+        for name in &missing {
+            self.report(&format!("{kind}: {name}: not found"));
+        }
+        i32::from(!missing.is_empty())
+    }
+    fn source(&mut self, args: &[String]) -> Result<i32, Unwind> {
+        let Some(path) = args.first() else {
+            self.report("source: filename argument required");
+            return Ok(2);
+        };
+        let source_code = match fs::read_to_string(path) {
+            Ok(source_code) => source_code,
+            Err(err) => {
+                self.report(&format!("source: {path}: {err}"));
+                return Ok(1);
+            }
+        };
+        let script = std::mem::replace(&mut self.script, path.clone());
+        let result = self.run_source(&source_code);
+        self.script = script;
+        result?;
+        Ok(self.env.last_status)
+    }
+    // set -e / -u / -f / -o pipefail, and +x forms to turn options back off
+    fn set(&mut self, args: &[String]) -> i32 {
+        let mut args = args.iter();
+        if args.len() == 0 {
+            for (name, enabled) in self.env.options.list() {
+                writeln!(
+                    self.stdout(),
+                    "{name}\t{}",
+                    if enabled { "on" } else { "off" }
+                )
+                .ok();
+            }
+            return 0;
+        }
+        while let Some(arg) = args.next() {
+            let (enable, flags) = match (arg.strip_prefix('-'), arg.strip_prefix('+')) {
+                (Some(flags), _) => (true, flags),
+                (_, Some(flags)) => (false, flags),
+                _ => {
+                    self.report(&format!("set: invalid argument: {arg}"));
+                    return 2;
+                }
+            };
+            for flag in flags.chars() {
+                let option = match flag {
+                    'e' => "errexit",
+                    'u' => "nounset",
+                    'f' => "noglob",
+                    'o' => match args.next() {
+                        Some(option) => option,
+                        None => {
+                            self.report("set: -o requires an option name");
+                            return 2;
+                        }
+                    },
+                    _ => {
+                        self.report(&format!("set: unknown flag: {flag}"));
+                        return 2;
+                    }
+                };
+                if !self.env.options.set(option, enable) {
+                    self.report(&format!("set: unknown option: {option}"));
+                    return 2;
+                }
+            }
+        }
+        0
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+// the terminal without line buffering or echo while a line is edited, put back on drop;
+// Ctrl-C and Ctrl-Z still raise signals
+struct RawMode(libc::termios);
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) };
+        Ok(RawMode(original))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    Ok((io::stdin().read(&mut byte)? == 1).then_some(byte[0]))
+}
+
+// whether a word typed after `before` would be a command name
+fn at_command_position(before: &str) -> bool {
+    let before = before.trim_end();
+    before.is_empty()
+        || ["|", "&&", "||", "&", "{", "(", "=>"]
+            .iter()
+            .any(|op| before.ends_with(op))
+}
+
+fn erase(stdout: &mut io::Stdout, text: &str) -> io::Result<()> {
+    for _ in text.chars() {
+        write!(stdout, "\x08 \x08")?;
+    }
+    Ok(())
+}
+
+// reads a line from the terminal, echoing it ourselves so that an abbreviation typed as a
+// command can be replaced by its expansion when Space follows it. `None` at Ctrl-D on an
+// empty line, like the end of input.
+pub(super) fn read_line(abbreviations: &BTreeMap<String, String>) -> io::Result<Option<String>> {
+    let _raw = RawMode::enable()?;
+    let mut stdout = io::stdout();
+    let mut line = String::new();
+    let mut pending = Vec::new(); // the bytes of a character not yet complete
+    while let Some(byte) = read_byte()? {
+        match byte {
+            b'\r' | b'\n' => {
+                writeln!(stdout)?;
+                line.push('\n');
+                return Ok(Some(line));
+            }
+            0x04 if line.is_empty() => return Ok(None),
+            0x7f | 0x08 => {
+                if let Some(ch) = line.pop() {
+                    erase(&mut stdout, &ch.to_string())?;
+                }
+            }
+            // Ctrl-U
+            0x15 => {
+                erase(&mut stdout, &line)?;
+                line.clear();
+            }
+            // arrow keys and the like aren't supported; skip the whole escape sequence
+            0x1b => {
+                if let Some(b'[' | b'O') = read_byte()? {
+                    while let Some(byte) = read_byte()? {
+                        if (0x40..=0x7e).contains(&byte) {
+                            break;
+                        }
+                    }
+                }
+            }
+            b' ' => {
+                let word = line.rsplit(char::is_whitespace).next().unwrap_or("");
+                let before = &line[..line.len() - word.len()];
+                if at_command_position(before)
+                    && let Some(expansion) = abbreviations.get(word)
+                {
+                    erase(&mut stdout, word)?;
+                    write!(stdout, "{expansion}")?;
+                    line.truncate(before.len());
+                    line.push_str(expansion);
+                }
+                write!(stdout, " ")?;
+                line.push(' ');
+            }
+            byte if byte < 0x20 => {}
+            byte => {
+                pending.push(byte);
+                if let Ok(text) = std::str::from_utf8(&pending) {
+                    write!(stdout, "{text}")?;
+                    line.push_str(text);
+                    pending.clear();
+                } else if pending.len() >= 4 {
+                    pending.clear();
+                }
+            }
+        }
+        stdout.flush()?;
+    }
+    Ok((!line.is_empty()).then_some(line))
+}
@@ -1,43 +1,239 @@
-use super::values::Value;
-use std::collections::HashMap;
+use super::interpreter::Span;
+use super::jobs::Job;
+use super::values::{Function, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::rc::Rc;
+
+// one block's variables; shared so closures can outlive the frame that made them
+pub type Scope = Rc<RefCell<HashMap<String, Value>>>;
 
 struct LocalEnv {
-    frames: Vec<Frame>
+    frames: Vec<Frame>,
 }
 
 struct GlobalEnv {
     variables: HashMap<String, Value>,
-    //functions: HashMap<String, Function>,
+    functions: HashMap<String, Rc<Function>>,
+    exports: HashSet<String>,
 }
 
 pub struct ShellEnv {
     globals: GlobalEnv,
-    locals: LocalEnv
+    locals: LocalEnv,
+    pub options: ShellOptions,
+    pub last_status: i32,
+    pub overrides: Vec<(String, String)>, // `NAME=value cmd` prefixes of running commands
+    pub jobs: Vec<Job>,                   // background and stopped jobs, by id
+    pub traps: BTreeMap<String, Vec<Rc<Function>>>, // `trap` handlers by event, in order
+    pub aliases: BTreeMap<String, String>, // expanded at command position as code is parsed
+    pub abbreviations: BTreeMap<String, String>, // expanded as they're typed at the prompt
 }
 
+// a function activation: the scopes it captured, followed by its own block scopes
 struct Frame {
     name: Option<String>,
-    vars: HashMap<String, Value>
+    call_site: Option<Span>, // `None` only for the top level
+    scopes: Vec<Scope>,
+    own: usize, // index of the function's outermost own scope
+}
+
+#[derive(Default)]
+pub struct ShellOptions {
+    pub errexit: bool,  // set -e: abort on unchecked command failure
+    pub pipefail: bool, // set -o pipefail: any failing stage fails the pipeline
+    pub nounset: bool,  // set -u: reading an undefined variable is an error
+    pub noglob: bool,   // set -f: pass glob patterns through unexpanded
+    pub nullglob: bool, // set -o nullglob: a glob with no matches expands to nothing
+}
+impl ShellOptions {
+    pub fn set(&mut self, name: &str, enabled: bool) -> bool {
+        match name {
+            "errexit" => self.errexit = enabled,
+            "pipefail" => self.pipefail = enabled,
+            "nounset" => self.nounset = enabled,
+            "noglob" => self.noglob = enabled,
+            "nullglob" => self.nullglob = enabled,
+            _ => return false,
+        }
+        true
+    }
+    pub fn list(&self) -> [(&'static str, bool); 5] {
+        [
+            ("errexit", self.errexit),
+            ("noglob", self.noglob),
+            ("nounset", self.nounset),
+            ("nullglob", self.nullglob),
+            ("pipefail", self.pipefail),
+        ]
+    }
 }
 impl ShellEnv {
     pub fn new() -> Self {
+        let mut globals = GlobalEnv::new();
+        globals.import_process_env();
+        let mut locals = LocalEnv::new();
+        locals.frames.push(Frame {
+            name: None,
+            call_site: None,
+            scopes: Vec::new(),
+            own: 0,
+        });
         ShellEnv {
-            globals: GlobalEnv::new(),
-            locals: LocalEnv::new()
+            globals,
+            locals,
+            options: ShellOptions::default(),
+            last_status: 0,
+            overrides: Vec::new(),
+            jobs: Vec::new(),
+            traps: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            abbreviations: BTreeMap::new(),
+        }
+    }
+    fn frame(&self) -> &Frame {
+        self.locals
+            .frames
+            .last()
+            .expect("the top-level frame is never popped")
+    }
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.locals
+            .frames
+            .last_mut()
+            .expect("the top-level frame is never popped")
+    }
+    // the innermost visible scope that binds `name`
+    fn find(&self, name: &str) -> Option<&Scope> {
+        self.frame()
+            .scopes
+            .iter()
+            .rev()
+            .find(|scope| scope.borrow().contains_key(name))
+    }
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match self.find(name) {
+            Some(scope) => scope.borrow().get(name).cloned(),
+            None => self.globals.variables.get(name).cloned(),
+        }
+    }
+    // plain assignment: rebinds the nearest existing variable, otherwise creates one
+    // at function level (or globally outside any function)
+    pub fn set(&mut self, name: &str, value: Value) {
+        if let Some(scope) = self.find(name) {
+            scope.borrow_mut().insert(name.to_string(), value);
+            return;
+        }
+        let frame = self.frame();
+        match frame.scopes.get(frame.own) {
+            Some(scope)
+                if self.locals.frames.len() > 1 && !self.globals.variables.contains_key(name) =>
+            {
+                scope.borrow_mut().insert(name.to_string(), value);
+            }
+            _ => {
+                self.globals.variables.insert(name.to_string(), value);
+            }
+        }
+    }
+    // `let`/`local`: binds in the innermost scope, shadowing outer variables
+    pub fn declare(&mut self, name: &str, value: Value) {
+        match self.frame().scopes.last() {
+            Some(scope) => {
+                scope.borrow_mut().insert(name.to_string(), value);
+            }
+            None => {
+                self.globals.variables.insert(name.to_string(), value);
+            }
         }
     }
+    pub fn unset(&mut self, name: &str) {
+        if let Some(scope) = self.find(name) {
+            scope.borrow_mut().remove(name);
+            return;
+        }
+        self.globals.variables.remove(name);
+        self.globals.exports.remove(name);
+    }
+    pub fn push_scope(&mut self) {
+        self.frame_mut().scopes.push(Scope::default());
+    }
+    pub fn pop_scope(&mut self) {
+        self.frame_mut().scopes.pop();
+    }
+    // the scopes a function defined here closes over
+    pub fn capture(&self) -> Vec<Scope> {
+        self.frame().scopes.clone()
+    }
+    // exported variables always live in the global scope
+    pub fn export(&mut self, name: &str, value: Option<Value>) {
+        if let Some(value) = value.or_else(|| self.get(name)) {
+            self.globals.variables.insert(name.to_string(), value);
+        }
+        self.globals.exports.insert(name.to_string());
+    }
+    // the complete environment of a child process
+    pub fn exported(&self) -> Vec<(String, String)> {
+        let mut exported: HashMap<String, String> = self
+            .globals
+            .exports
+            .iter()
+            .filter_map(|name| Some((name.clone(), self.globals.variables.get(name)?.to_string())))
+            .collect();
+        exported.extend(self.overrides.iter().cloned());
+        let mut exported: Vec<(String, String)> = exported.into_iter().collect();
+        exported.sort();
+        exported
+    }
+    pub fn get_function(&self, name: &str) -> Option<Rc<Function>> {
+        self.globals.functions.get(name).cloned()
+    }
+    pub fn define_function(&mut self, name: &str, function: Rc<Function>) {
+        self.globals.functions.insert(name.to_string(), function);
+    }
+    pub fn push_frame(&mut self, name: Option<String>, captured: Vec<Scope>, call_site: Span) {
+        let own = captured.len();
+        let mut scopes = captured;
+        scopes.push(Scope::default());
+        self.locals.frames.push(Frame {
+            name,
+            call_site: Some(call_site),
+            scopes,
+            own,
+        });
+    }
+    pub fn pop_frame(&mut self) {
+        self.locals.frames.pop();
+    }
+    // active calls innermost first, as (function name, where it was called from)
+    pub fn stack(&self) -> impl Iterator<Item = (Option<&str>, Option<&Span>)> {
+        self.locals
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| (frame.name.as_deref(), frame.call_site.as_ref()))
+    }
 }
 impl GlobalEnv {
     pub fn new() -> Self {
         GlobalEnv {
-            variables: HashMap::new()
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            exports: HashSet::new(),
+        }
+    }
+    fn import_process_env(&mut self) {
+        for (name, value) in env::vars_os() {
+            if let (Ok(name), Ok(value)) = (name.into_string(), value.into_string()) {
+                self.exports.insert(name.clone());
+                self.variables.insert(name, Value::String(value));
+            }
         }
     }
 }
 impl LocalEnv {
     pub fn new() -> Self {
-        LocalEnv {
-            frames: Vec::new()
-        }
+        LocalEnv { frames: Vec::new() }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,585 @@
+use super::Shell;
+use super::ast::{Expr, RedirectKind, Redirection};
+use super::interpreter::{EvalResult, ShellError, Unwind};
+use super::jobs::{Job, describe, reset_signals};
+use super::patterns::is_glob;
+use super::values::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::thread;
+
+// where the current command reads and writes; `None` inherits the shell's own stream
+#[derive(Default)]
+pub struct Io {
+    pub stdin: Option<OwnedFd>,
+    pub stdout: Option<OwnedFd>,
+    pub stderr: Option<OwnedFd>,
+    pub others: Vec<(i32, Option<OwnedFd>)>, // descriptors past stderr, and closed ones as `None`
+}
+
+type Overrides = Vec<(String, String)>;
+
+struct ExternalCommand {
+    overrides: Overrides,
+    name: String,
+    args: Vec<String>,
+}
+
+enum Stage {
+    Running(i32), // a pid
+    Finished(i32),
+}
+
+impl Io {
+    fn try_clone(&self) -> io::Result<Io> {
+        let dup = |fd: &Option<OwnedFd>| fd.as_ref().map(OwnedFd::try_clone).transpose();
+        Ok(Io {
+            stdin: dup(&self.stdin)?,
+            stdout: dup(&self.stdout)?,
+            stderr: dup(&self.stderr)?,
+            others: self
+                .others
+                .iter()
+                .map(|(fd, file)| Ok((*fd, dup(file)?)))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+    fn is_closed(&self, fd: i32) -> bool {
+        self.others
+            .iter()
+            .any(|(other, file)| *other == fd && file.is_none())
+    }
+    // points `fd` at `file` for what runs next, or closes it when `None`
+    fn set(&mut self, fd: i32, file: Option<OwnedFd>) -> io::Result<()> {
+        self.others.retain(|(other, _)| *other != fd);
+        match (fd, file) {
+            (0, Some(file)) => self.stdin = Some(file),
+            (1, Some(file)) => self.stdout = Some(file),
+            (2, Some(file)) => self.stderr = Some(file),
+            // kept out of the way of the descriptor numbers commands get them as
+            (_, Some(file)) => self
+                .others
+                .push((fd, Some(dup_above(file.as_raw_fd(), true)?))),
+            (_, None) => {
+                match fd {
+                    0 => self.stdin = None,
+                    1 => self.stdout = None,
+                    2 => self.stderr = None,
+                    _ => {}
+                }
+                self.others.push((fd, None));
+            }
+        }
+        Ok(())
+    }
+    // a copy of whatever `fd` refers to now, for `2->&1`
+    fn dup(&self, fd: i32) -> io::Result<OwnedFd> {
+        if self.is_closed(fd) {
+            return Err(io::Error::from_raw_os_error(libc::EBADF));
+        }
+        let redirected = match fd {
+            0 => self.stdin.as_ref(),
+            1 => self.stdout.as_ref(),
+            2 => self.stderr.as_ref(),
+            _ => self
+                .others
+                .iter()
+                .find(|(other, _)| *other == fd)
+                .and_then(|(_, file)| file.as_ref()),
+        };
+        match redirected {
+            Some(file) => dup_above(file.as_raw_fd(), true),
+            None => dup_above(fd, true),
+        }
+    }
+}
+
+// a copy of `fd` numbered from 10 up, where redirections like `3<- file` won't land on it
+pub(super) fn dup_above(fd: RawFd, cloexec: bool) -> io::Result<OwnedFd> {
+    let command = if cloexec {
+        libc::F_DUPFD_CLOEXEC
+    } else {
+        libc::F_DUPFD
+    };
+    match unsafe { libc::fcntl(fd, command, 10) } {
+        -1 => Err(io::Error::last_os_error()),
+        copy => Ok(unsafe { OwnedFd::from_raw_fd(copy) }),
+    }
+}
+
+pub(super) fn io_error(err: io::Error) -> Unwind {
+    Unwind::error("io", err.to_string())
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_alphabetic() || first == '_')
+        && chars.all(|next| next.is_alphanumeric() || next == '_')
+}
+
+// command-line arguments; lists spread into one argument per item
+pub fn argv(args: &[Value]) -> Vec<String> {
+    args.iter()
+        .flat_map(|arg| match arg {
+            Value::List(items) => items.iter().map(ToString::to_string).collect(),
+            Value::Range(range) => range.iter().map(|x| x.to_string()).collect(),
+            other => vec![other.to_string()],
+        })
+        .collect()
+}
+
+fn flatten_pipe<'a>(expr: &'a Expr, stages: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Pipe { left, right } => {
+            flatten_pipe(left, stages);
+            flatten_pipe(right, stages);
+        }
+        _ => stages.push(expr),
+    }
+}
+
+// a pipe to read `bytes` from, for here-docs and buffered pipeline stages. What fits in the
+// pipe is written up front, so a forked shell can't inherit an open writer and wait for an
+// end that never comes; more than that is written from a thread as it's read.
+fn feed(bytes: Vec<u8>) -> Result<OwnedFd, Unwind> {
+    let (reader, mut writer) = io::pipe().map_err(io_error)?;
+    if bytes.len() < 65536 {
+        writer.write_all(&bytes).map_err(io_error)?;
+    } else {
+        thread::spawn(move || writer.write_all(&bytes));
+    }
+    Ok(OwnedFd::from(reader))
+}
+
+impl Shell {
+    // builtins' output goes nowhere once its descriptor is closed with `1->&-`
+    pub(super) fn stdout(&self) -> Box<dyn Write> {
+        match self.io.stdout.as_ref().map(OwnedFd::try_clone) {
+            Some(Ok(fd)) => Box::new(File::from(fd)),
+            _ if self.io.is_closed(1) => Box::new(io::sink()),
+            _ => Box::new(io::stdout()),
+        }
+    }
+    pub(super) fn stderr(&self) -> Box<dyn Write> {
+        match self.io.stderr.as_ref().map(OwnedFd::try_clone) {
+            Some(Ok(fd)) => Box::new(File::from(fd)),
+            _ if self.io.is_closed(2) => Box::new(io::sink()),
+            _ => Box::new(io::stderr()),
+        }
+    }
+    pub(super) fn report(&self, message: &str) {
+        writeln!(self.stderr(), "shucks: {message}").ok();
+    }
+    // an uncaught error with where it happened and the calls it unwound through
+    pub(super) fn report_error(&self, err: &ShellError) {
+        // the user pressed Ctrl-C, so there's nothing to explain
+        if err.kind == "interrupt" {
+            return;
+        }
+        match &err.span {
+            Some(span) => self.report(&format!("{span}: {}", err.message)),
+            None => self.report(&err.message),
+        }
+        let mut stderr = self.stderr();
+        for (name, call_site) in &err.trace {
+            writeln!(stderr, "  in {name}, called at {call_site}").ok();
+        }
+    }
+    // writes a plain value the way a command would print it
+    pub(super) fn emit(&self, value: &Value) {
+        if !matches!(value, Value::None | Value::Status(_)) {
+            writeln!(self.stdout(), "{value}").ok();
+        }
+    }
+    pub(super) fn run_command(&mut self, command: &Expr, args: &[Expr]) -> EvalResult {
+        let (overrides, mut words) = self.command_words(command, args)?;
+        if words.is_empty() {
+            for (name, value) in overrides {
+                self.env.set(&name, Value::String(value));
+            }
+            return Ok(Value::None);
+        }
+        let name = words.remove(0).to_string();
+        let depth = self.env.overrides.len();
+        self.env.overrides.extend(overrides);
+        let result = self.execute(&name, words);
+        self.env.overrides.truncate(depth);
+        result
+    }
+    // runs a function, builtin or external program and applies errexit to its status
+    pub(super) fn execute(&mut self, name: &str, args: Vec<Value>) -> EvalResult {
+        let result = self.dispatch(name, args)?;
+        self.check_status(name, result)
+    }
+    fn dispatch(&mut self, name: &str, args: Vec<Value>) -> EvalResult {
+        if let Some(Value::Function(function)) = self.env.get(name) {
+            return self.call_function(&function, args);
+        }
+        if let Some(function) = self.env.get_function(name) {
+            return self.call_function(&function, args);
+        }
+        if Shell::is_builtin_function(name) {
+            return self.call_builtin_function(name, args);
+        }
+        if Shell::is_builtin(name) {
+            return Ok(Value::Status(self.run_builtin(name, &args)?));
+        }
+        let args = argv(&args);
+        let status = match self.spawn(name, &args, 0) {
+            Ok(child) => {
+                let pid = child.id() as i32;
+                let command = format!("{name} {}", args.join(" "));
+                match self.wait_foreground(Job::new(pid, vec![pid], command.trim_end().into())) {
+                    Some(statuses) => statuses[0],
+                    None => 128 + libc::SIGTSTP,
+                }
+            }
+            Err(err) => self.spawn_failed(name, err),
+        };
+        Ok(Value::Status(status))
+    }
+    pub(super) fn check_status(&mut self, name: &str, result: Value) -> EvalResult {
+        if let Value::Status(code) = result {
+            self.env.last_status = code;
+            let throws = self.env.options.errexit || self.trying > 0;
+            if code != 0 && throws && self.conditions == 0 {
+                let message = match code {
+                    129.. => format!("{name}: killed by signal {}", code - 128),
+                    _ => format!("{name}: exited with status {code}"),
+                };
+                return Err(Unwind::Error(ShellError {
+                    message,
+                    kind: "command".to_string(),
+                    status: code,
+                    span: None,
+                    trace: Vec::new(),
+                }));
+            }
+        }
+        Ok(result)
+    }
+    // splits leading `NAME=value` words off the program name and its arguments
+    fn command_words(
+        &mut self,
+        command: &Expr,
+        args: &[Expr],
+    ) -> Result<(Overrides, Vec<Value>), Unwind> {
+        let mut overrides = Vec::new();
+        let mut words = Vec::new();
+        for word in std::iter::once(command).chain(args) {
+            if let (true, Expr::ShellWord(text)) = (words.is_empty(), word)
+                && let Some((name, value)) = text.split_once('=')
+                && is_variable_name(name)
+            {
+                let value = self.interpolate(&self.expand_tilde(value))?;
+                overrides.push((name.to_string(), value));
+                continue;
+            }
+            // unquoted arguments like `*.log` become the sorted paths they match
+            if let (false, Expr::ShellWord(text)) = (words.is_empty(), word)
+                && !self.env.options.noglob
+                && is_glob(&text.replace("$?", ""))
+            {
+                let pattern = self.interpolate(&self.expand_tilde(text))?;
+                let paths = self.expand_glob(&pattern);
+                if paths.is_empty() && !self.env.options.nullglob {
+                    return Err(Unwind::error("glob", format!("no matches for {pattern}")));
+                }
+                words.extend(paths.into_iter().map(Value::String));
+                continue;
+            }
+            words.push(self.word(word)?);
+        }
+        Ok((overrides, words))
+    }
+    // identifiers in argument position are plain words, not variable reads
+    pub(super) fn word(&mut self, expr: &Expr) -> EvalResult {
+        match expr {
+            Expr::Identifier(name) => Ok(Value::String(name.clone())),
+            _ => self.eval(expr),
+        }
+    }
+    // the program and arguments of an expression that would run an external command
+    fn external_command(&mut self, expr: &Expr) -> Result<Option<ExternalCommand>, Unwind> {
+        let (overrides, mut words) = match expr {
+            Expr::CommandCall { command, args } => self.command_words(command, args)?,
+            Expr::Identifier(name) if self.env.get(name).is_none() => {
+                (Vec::new(), vec![Value::String(name.clone())])
+            }
+            Expr::ShellWord(_) => self.command_words(expr, &[])?,
+            _ => return Ok(None),
+        };
+        if words.is_empty() {
+            return Ok(None);
+        }
+        let name = words.remove(0).to_string();
+        if self.is_internal(&name) {
+            return Ok(None);
+        }
+        Ok(Some(ExternalCommand {
+            overrides,
+            name,
+            args: argv(&words),
+        }))
+    }
+    fn is_internal(&self, name: &str) -> bool {
+        matches!(self.env.get(name), Some(Value::Function(_)))
+            || self.env.get_function(name).is_some()
+            || Shell::is_builtin_function(name)
+            || Shell::is_builtin(name)
+    }
+    // under job control each foreground job gets its own process group, `pgid` or a new one
+    fn spawn(&self, name: &str, args: &[String], pgid: i32) -> io::Result<Child> {
+        let mut command = Command::new(name);
+        command.args(args).env_clear().envs(self.env.exported());
+        if self.job_control {
+            command.process_group(pgid);
+            unsafe {
+                command.pre_exec(|| {
+                    reset_signals();
+                    Ok(())
+                });
+            }
+        }
+        if let Some(fd) = &self.io.stdin {
+            command.stdin(fd.try_clone()?);
+        }
+        if let Some(fd) = &self.io.stdout {
+            command.stdout(fd.try_clone()?);
+        }
+        if let Some(fd) = &self.io.stderr {
+            command.stderr(fd.try_clone()?);
+        }
+        let others: Vec<_> = (self.io.others.iter())
+            .map(|(fd, file)| (*fd, file.as_ref().map(AsRawFd::as_raw_fd)))
+            .collect();
+        if !others.is_empty() {
+            // every source is copied above all the descriptors involved before any is put in
+            // place, so one landing on `fd` can't clobber another's source, as `11-> a 10-> b`
+            // would when `a` is kept at 10; the copies are allocated here, not after the fork
+            let floor = (others.iter())
+                .flat_map(|(fd, file)| [*fd, file.unwrap_or(0)])
+                .max()
+                .unwrap_or(0)
+                + 1;
+            let mut copies = vec![-1; others.len()];
+            unsafe {
+                command.pre_exec(move || {
+                    for ((_, file), copy) in others.iter().zip(&mut copies) {
+                        if let Some(source) = *file {
+                            *copy = libc::fcntl(source, libc::F_DUPFD_CLOEXEC, floor);
+                            if *copy < 0 {
+                                return Err(io::Error::last_os_error());
+                            }
+                        }
+                    }
+                    for ((fd, file), copy) in others.iter().zip(&copies) {
+                        match file {
+                            Some(_) => {
+                                if libc::dup2(*copy, *fd) < 0 {
+                                    return Err(io::Error::last_os_error());
+                                }
+                            }
+                            None => {
+                                libc::close(*fd);
+                            }
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+        command.spawn()
+    }
+    fn spawn_failed(&self, name: &str, err: io::Error) -> i32 {
+        match err.kind() {
+            io::ErrorKind::NotFound => {
+                self.report(&format!("command not found: {name}"));
+                127
+            }
+            _ => {
+                self.report(&format!("{name}: {err}"));
+                126
+            }
+        }
+    }
+    pub(super) fn run_pipeline(&mut self, expr: &Expr) -> EvalResult {
+        let mut stages = Vec::new();
+        flatten_pipe(expr, &mut stages);
+        let saved = self.io.try_clone().map_err(io_error)?;
+        let mut input = saved.try_clone().map_err(io_error)?.stdin;
+        let mut running = Vec::new();
+        let mut failure = None;
+        let mut pgid = 0;
+        for (i, stage) in stages.iter().enumerate() {
+            let last = i + 1 == stages.len();
+            let (next_input, output) = if last {
+                (None, saved.try_clone().map_err(io_error)?.stdout)
+            } else {
+                let (reader, writer) = io::pipe().map_err(io_error)?;
+                (Some(OwnedFd::from(reader)), Some(OwnedFd::from(writer)))
+            };
+            self.io = Io {
+                stdin: input.take(),
+                stdout: output,
+                ..saved.try_clone().map_err(io_error)?
+            };
+            input = next_input;
+            // a stage's own redirections, as in `make 2->&1 | tee log`, go on top of its pipes
+            let (command, redirects) = match stage {
+                Expr::Redirect { expr, redirects } => (&**expr, &redirects[..]),
+                _ => (*stage, &[][..]),
+            };
+            let external = match self.external_command(command) {
+                Ok(Some(external)) => self.apply_redirects(redirects).map(|_| Some(external)),
+                other => other,
+            };
+            let external = match external {
+                Ok(external) => external,
+                Err(err) => {
+                    failure = Some(err);
+                    break;
+                }
+            };
+            if let Some(external) = external {
+                let depth = self.env.overrides.len();
+                self.env.overrides.extend(external.overrides);
+                match self.spawn(&external.name, &external.args, pgid) {
+                    Ok(child) => {
+                        if pgid == 0 {
+                            pgid = child.id() as i32;
+                        }
+                        running.push(Stage::Running(child.id() as i32))
+                    }
+                    Err(err) => {
+                        running.push(Stage::Finished(self.spawn_failed(&external.name, err)))
+                    }
+                }
+                self.env.overrides.truncate(depth);
+                continue;
+            }
+            // an in-process stage feeding another runs in a forked copy of the shell, streaming
+            // into the next stage as it goes
+            if !last {
+                let pid = self.fork_stage(stage, pgid, &mut input);
+                self.io = Io::default();
+                match pid {
+                    Ok(pid) => {
+                        if pgid == 0 {
+                            pgid = pid;
+                        }
+                        running.push(Stage::Running(pid));
+                        continue;
+                    }
+                    Err(err) => {
+                        failure = Some(err);
+                        break;
+                    }
+                }
+            }
+            let result = self.eval_statement(stage);
+            if let Ok(value) = &result {
+                self.emit(value);
+            }
+            self.io = Io::default();
+            match result {
+                Ok(Value::Status(code)) => running.push(Stage::Finished(code)),
+                Ok(_) => running.push(Stage::Finished(0)),
+                Err(err) => {
+                    failure = Some(err);
+                    break;
+                }
+            }
+        }
+        self.io = saved;
+        drop(input);
+        let pids: Vec<_> = running
+            .iter()
+            .filter_map(|stage| match stage {
+                Stage::Running(pid) => Some(*pid),
+                Stage::Finished(_) => None,
+            })
+            .collect();
+        // a stopped pipeline reports every external stage as suspended
+        let mut waited = match pids.is_empty() {
+            true => Some(Vec::new()),
+            false => self.wait_foreground(Job::new(pgid, pids, describe(expr))),
+        }
+        .map(Vec::into_iter);
+        let mut statuses = Vec::new();
+        for stage in running {
+            statuses.push(match stage {
+                Stage::Running(_) => match &mut waited {
+                    Some(waited) => waited.next().unwrap_or(0),
+                    None => 128 + libc::SIGTSTP,
+                },
+                Stage::Finished(code) => code,
+            });
+        }
+        if let Some(err) = failure {
+            return Err(err);
+        }
+        let status = if self.env.options.pipefail {
+            statuses
+                .iter()
+                .rev()
+                .find(|code| **code != 0)
+                .copied()
+                .unwrap_or(0)
+        } else {
+            statuses.last().copied().unwrap_or(0)
+        };
+        self.check_status("pipeline", Value::Status(status))
+    }
+    pub(super) fn run_redirect(&mut self, expr: &Expr, redirects: &[Redirection]) -> EvalResult {
+        let saved = self.io.try_clone().map_err(io_error)?;
+        let result = self
+            .apply_redirects(redirects)
+            .and_then(|_| self.eval_statement(expr));
+        if let Ok(value) = &result {
+            self.emit(value);
+        }
+        self.io = saved;
+        result
+    }
+    // points the descriptors at their targets, one redirection after another
+    fn apply_redirects(&mut self, redirects: &[Redirection]) -> Result<(), Unwind> {
+        for redirect in redirects {
+            let file = match &redirect.kind {
+                RedirectKind::HereString(value) => {
+                    let text = format!("{}\n", self.eval(value)?);
+                    Some(feed(text.into_bytes())?)
+                }
+                RedirectKind::HereDoc(body) => Some(feed(self.interpolate(body)?.into_bytes())?),
+                RedirectKind::RawHereDoc(body) => Some(feed(body.clone().into_bytes())?),
+                RedirectKind::Dup(source) => Some(
+                    self.io
+                        .dup(*source)
+                        .map_err(|err| Unwind::error("io", format!("{source}: {err}")))?,
+                ),
+                RedirectKind::Close => None,
+                RedirectKind::In(target)
+                | RedirectKind::Out(target)
+                | RedirectKind::Append(target) => {
+                    let path = self.word(target)?.to_string();
+                    let file = match &redirect.kind {
+                        RedirectKind::In(_) => File::open(&path),
+                        RedirectKind::Append(_) => {
+                            OpenOptions::new().append(true).create(true).open(&path)
+                        }
+                        _ => File::create(&path),
+                    }
+                    .map_err(|err| Unwind::error("io", format!("{path}: {err}")))?;
+                    Some(OwnedFd::from(file))
+                }
+            };
+            self.io.set(redirect.fd, file).map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use super::Shell;
+use super::interpreter::{EvalResult, ShellError, Unwind};
+use super::values::{Function, Value};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+// builtins that take and return values, called like `map(xs, f)`
+const BUILTIN_FUNCTIONS: &[&str] = &[
+    "len", "int", "float", "error", "map", "filter", "reduce", "sort_by", "replace", "split",
+    "find_all", "caller", "trap",
+];
+
+// compiled patterns kept before the cache is cleared, so loops over generated patterns stay bounded
+const REGEX_CACHE_SIZE: usize = 64;
+
+fn expect_args<const N: usize>(name: &str, args: Vec<Value>) -> Result<[Value; N], Unwind> {
+    args.try_into().map_err(|args: Vec<Value>| {
+        Unwind::error(
+            "type",
+            format!("{name} expects {N} arguments, got {}", args.len()),
+        )
+    })
+}
+
+fn list_arg(name: &str, value: Value) -> Result<Vec<Value>, Unwind> {
+    match value {
+        Value::List(items) => Ok(items),
+        Value::Range(range) => Ok(range.iter().map(Value::Int).collect()),
+        other => Err(Unwind::error(
+            "type",
+            format!("{name} expects a list, got a {}", other.type_name()),
+        )),
+    }
+}
+
+fn string_arg(name: &str, value: Value) -> Result<String, Unwind> {
+    match value {
+        Value::String(text) => Ok(text),
+        other => Err(Unwind::error(
+            "type",
+            format!("{name} expects a string, got a {}", other.type_name()),
+        )),
+    }
+}
+
+fn function_arg(name: &str, value: Value) -> Result<Rc<Function>, Unwind> {
+    match value {
+        Value::Function(function) => Ok(function),
+        other => Err(Unwind::error(
+            "type",
+            format!("{name} expects a function, got a {}", other.type_name()),
+        )),
+    }
+}
+
+impl Shell {
+    pub(super) fn is_builtin_function(name: &str) -> bool {
+        BUILTIN_FUNCTIONS.contains(&name)
+    }
+    pub(super) fn regex(&mut self, pattern: &str) -> Result<Regex, Unwind> {
+        if let Some(regex) = self.regexes.get(pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = Regex::new(pattern)
+            .map_err(|err| Unwind::error("regex", format!("invalid regex \"{pattern}\": {err}")))?;
+        if self.regexes.len() >= REGEX_CACHE_SIZE {
+            self.regexes.clear();
+        }
+        self.regexes.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+    // `text =~ pattern`, leaving the whole match and its groups in `MATCH`
+    pub(super) fn match_regex(&mut self, text: &str, pattern: &str) -> EvalResult {
+        let regex = self.regex(pattern)?;
+        let captures = regex.captures(text);
+        let groups = captures.iter().flat_map(|captures| {
+            captures.iter().map(|group| match group {
+                Some(group) => Value::String(group.as_str().to_string()),
+                None => Value::None,
+            })
+        });
+        self.env.set("MATCH", Value::List(groups.collect()));
+        Ok(Value::Boolean(captures.is_some()))
+    }
+    pub(super) fn call_builtin_function(&mut self, name: &str, args: Vec<Value>) -> EvalResult {
+        match name {
+            "len" => {
+                let [value] = expect_args(name, args)?;
+                let len = match &value {
+                    Value::List(items) => items.len() as u128,
+                    Value::Map(entries) => entries.len() as u128,
+                    Value::Range(range) => range.len(),
+                    Value::String(text) => text.chars().count() as u128,
+                    other => {
+                        return Err(Unwind::error(
+                            "type",
+                            format!(
+                                "len expects a list, map, range or string, got a {}",
+                                other.type_name()
+                            ),
+                        ));
+                    }
+                };
+                match i64::try_from(len) {
+                    Ok(len) => Ok(Value::Int(len)),
+                    Err(_) => Err(Unwind::error(
+                        "overflow",
+                        format!("len: {len} items don't fit in an int"),
+                    )),
+                }
+            }
+            "int" => {
+                let [value] = expect_args(name, args)?;
+                match value {
+                    Value::Int(x) => Ok(Value::Int(x)),
+                    // truncates toward zero; NaN and out-of-range floats have no int
+                    Value::Float(x) if x.is_finite() && x.trunc().abs() < i64::MAX as f64 => {
+                        Ok(Value::Int(x.trunc() as i64))
+                    }
+                    Value::String(text) if text.trim().parse::<i64>().is_ok() => {
+                        Ok(Value::Int(text.trim().parse().unwrap()))
+                    }
+                    Value::Boolean(x) => Ok(Value::Int(x as i64)),
+                    other => Err(Unwind::error(
+                        "type",
+                        format!("int: cannot convert the {} `{other}`", other.type_name()),
+                    )),
+                }
+            }
+            "float" => {
+                let [value] = expect_args(name, args)?;
+                match value {
+                    Value::Int(x) => Ok(Value::Float(x as f64)),
+                    Value::Float(x) => Ok(Value::Float(x)),
+                    Value::String(text) if text.trim().parse::<f64>().is_ok() => {
+                        Ok(Value::Float(text.trim().parse().unwrap()))
+                    }
+                    other => Err(Unwind::error(
+                        "type",
+                        format!("float: cannot convert the {} `{other}`", other.type_name()),
+                    )),
+                }
+            }
+            "error" => {
+                // error(message), error(message, kind) or error(message, kind, status)
+                let mut args = args.into_iter();
+                let Some(message) = args.next() else {
+                    return Err(Unwind::error("type", "error expects a message"));
+                };
+                let kind = match args.next() {
+                    Some(kind) => string_arg(name, kind)?,
+                    None => "error".to_string(),
+                };
+                let status = match args.next() {
+                    // only what a process can exit with, so it reads back the same from `$?`
+                    Some(Value::Int(status @ 0..=255)) => status as i32,
+                    Some(Value::Int(status)) => {
+                        return Err(Unwind::error(
+                            "value",
+                            format!("error status {status} out of range 0..=255"),
+                        ));
+                    }
+                    Some(other) => {
+                        return Err(Unwind::error(
+                            "type",
+                            format!("error expects an int status, got a {}", other.type_name()),
+                        ));
+                    }
+                    None => 1,
+                };
+                Ok(Value::Error(Rc::new(ShellError {
+                    message: message.to_string(),
+                    kind,
+                    status,
+                    span: None,
+                    trace: Vec::new(),
+                })))
+            }
+            "map" => {
+                let [list, function] = expect_args(name, args)?;
+                let function = function_arg(name, function)?;
+                let items = list_arg(name, list)?
+                    .into_iter()
+                    .map(|item| self.call_function(&function, vec![item]))
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::List(items))
+            }
+            "filter" => {
+                let [list, function] = expect_args(name, args)?;
+                let function = function_arg(name, function)?;
+                let mut kept = Vec::new();
+                for item in list_arg(name, list)? {
+                    if self
+                        .call_function(&function, vec![item.clone()])?
+                        .is_truthy()
+                    {
+                        kept.push(item);
+                    }
+                }
+                Ok(Value::List(kept))
+            }
+            "reduce" => {
+                // reduce(xs, f) starts from the first item, reduce(xs, f, init) from `init`
+                let (list, function, init) = match args.len() {
+                    2 => {
+                        let [list, function] = expect_args(name, args)?;
+                        (list, function, None)
+                    }
+                    _ => {
+                        let [list, function, init] = expect_args(name, args)?;
+                        (list, function, Some(init))
+                    }
+                };
+                let function = function_arg(name, function)?;
+                let mut items = list_arg(name, list)?.into_iter();
+                let Some(mut acc) = init.or_else(|| items.next()) else {
+                    return Err(Unwind::error(
+                        "value",
+                        "reduce of an empty list with no initial value",
+                    ));
+                };
+                for item in items {
+                    acc = self.call_function(&function, vec![acc, item])?;
+                }
+                Ok(acc)
+            }
+            "sort_by" => {
+                let [list, function] = expect_args(name, args)?;
+                let function = function_arg(name, function)?;
+                let mut keyed = Vec::new();
+                for item in list_arg(name, list)? {
+                    keyed.push((self.call_function(&function, vec![item.clone()])?, item));
+                }
+                let mut incomparable = None;
+                keyed.sort_by(|(a, _), (b, _)| {
+                    a.compare(b).unwrap_or_else(|| {
+                        incomparable.get_or_insert((a.type_name(), b.type_name()));
+                        Ordering::Equal
+                    })
+                });
+                if let Some((a, b)) = incomparable {
+                    return Err(Unwind::error(
+                        "type",
+                        format!("sort_by cannot compare {a} and {b}"),
+                    ));
+                }
+                Ok(Value::List(
+                    keyed.into_iter().map(|(_, item)| item).collect(),
+                ))
+            }
+            "replace" => {
+                // `$1` or `${1}` in the replacement refers to a group by number; strings
+                // interpolate `${name}` before it gets here, so named groups go by number too
+                let [text, pattern, replacement] = expect_args(name, args)?;
+                let text = string_arg(name, text)?;
+                let regex = self.regex(&string_arg(name, pattern)?)?;
+                let replacement = string_arg(name, replacement)?;
+                Ok(Value::String(
+                    regex.replace_all(&text, replacement.as_str()).into_owned(),
+                ))
+            }
+            "split" => {
+                let [text, pattern] = expect_args(name, args)?;
+                let text = string_arg(name, text)?;
+                let regex = self.regex(&string_arg(name, pattern)?)?;
+                Ok(Value::List(
+                    regex
+                        .split(&text)
+                        .map(|part| Value::String(part.to_string()))
+                        .collect(),
+                ))
+            }
+            "find_all" => {
+                let [text, pattern] = expect_args(name, args)?;
+                let text = string_arg(name, text)?;
+                let regex = self.regex(&string_arg(name, pattern)?)?;
+                Ok(Value::List(
+                    regex
+                        .find_iter(&text)
+                        .map(|found| Value::String(found.as_str().to_string()))
+                        .collect(),
+                ))
+            }
+            "caller" => {
+                // caller() is who called the current function, caller(1) who called that one
+                let depth = match args.len() {
+                    0 => 0,
+                    _ => match expect_args(name, args)? {
+                        [Value::Int(depth)] if depth >= 0 => depth as usize,
+                        [other] => {
+                            return Err(Unwind::error(
+                                "type",
+                                format!("caller expects a depth of at least 0, got `{other}`"),
+                            ));
+                        }
+                    },
+                };
+                let mut stack = self.env.stack().skip(depth);
+                let Some((_, Some(call_site))) = stack.next() else {
+                    return Ok(Value::None);
+                };
+                let function = stack.next().and_then(|(name, _)| name).unwrap_or("main");
+                Ok(Value::Map(BTreeMap::from([
+                    ("name".to_string(), Value::String(function.to_string())),
+                    ("file".to_string(), Value::String(call_site.file.clone())),
+                    ("line".to_string(), Value::Int(call_site.line as i64)),
+                ])))
+            }
+            "trap" => self.trap(args),
+            _ => unreachable!("{name} is not a builtin function"),
+        }
+    }
+}
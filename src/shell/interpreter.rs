@@ -0,0 +1,806 @@
+use super::Shell;
+use super::ast::{Ast, BinaryOp, Expr, Pattern, UnaryOp};
+use super::patterns::Bind;
+use super::values::{Function, Range, Value};
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellError {
+    pub message: String,
+    pub kind: String, // "command", "index", "type", ... or whatever a script throws
+    pub status: i32,
+    pub span: Option<Span>, // filled in by the innermost statement it passes through
+    pub trace: Vec<(String, Span)>, // functions it unwound through and their call sites
+}
+
+impl ShellError {
+    // what a caught error exposes, as in `e["kind"]`
+    pub fn field(&self, name: &str) -> Option<Value> {
+        match name {
+            "message" => Some(Value::String(self.message.clone())),
+            "kind" => Some(Value::String(self.kind.clone())),
+            "status" => Some(Value::Int(self.status.into())),
+            "span" => Some(match &self.span {
+                Some(span) => Value::String(span.to_string()),
+                None => Value::None,
+            }),
+            "trace" => Some(Value::List(
+                self.trace
+                    .iter()
+                    .map(|(name, call_site)| Value::String(format!("{name} at {call_site}")))
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Unwind {
+    Error(ShellError),
+    Return(Value),
+    Exit(i32),
+}
+
+pub type EvalResult = Result<Value, Unwind>;
+
+impl Unwind {
+    pub fn error(kind: &str, message: impl Into<String>) -> Self {
+        Unwind::Error(ShellError {
+            message: message.into(),
+            kind: kind.to_string(),
+            status: 1,
+            span: None,
+            trace: Vec::new(),
+        })
+    }
+}
+
+impl Shell {
+    pub(super) fn evaluate(&mut self, ast: &Ast) -> EvalResult {
+        let mut result = Value::None;
+        for expr in &ast.body {
+            result = match self.eval_statement(expr) {
+                Err(Unwind::Return(value)) => return Ok(value),
+                other => other?,
+            };
+        }
+        Ok(result)
+    }
+    // bare words in statement position run as commands unless they name a variable
+    pub(super) fn eval_statement(&mut self, expr: &Expr) -> EvalResult {
+        match expr {
+            Expr::Statement { line, expr } => {
+                self.line = *line;
+                let depth = self.substitutions.len();
+                let result = self.check_signals().and_then(|_| self.eval_statement(expr));
+                self.finish_substitutions(depth);
+                match result {
+                    Err(Unwind::Error(mut err)) if err.span.is_none() => {
+                        err.span = Some(Span {
+                            file: self.script.clone(),
+                            line: *line,
+                        });
+                        Err(Unwind::Error(err))
+                    }
+                    other => other,
+                }
+            }
+            Expr::Identifier(name) if self.env.get(name).is_none() => self.run_command(expr, &[]),
+            Expr::ShellWord(_) => self.run_command(expr, &[]),
+            _ => self.eval(expr),
+        }
+    }
+    // failures here are handled by the script, so they never trip errexit
+    fn eval_checked(&mut self, expr: &Expr) -> EvalResult {
+        self.conditions += 1;
+        let result = self.eval_statement(expr);
+        self.conditions -= 1;
+        result
+    }
+    pub(super) fn eval(&mut self, expr: &Expr) -> EvalResult {
+        match expr {
+            Expr::Literal(Value::String(x)) => Ok(Value::String(self.interpolate(x)?)),
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Identifier(name) => self.lookup(name),
+            Expr::ShellWord(word) => Ok(Value::String(self.interpolate(&self.expand_tilde(word))?)),
+            Expr::Assign { name, value } => {
+                let value = self.eval(value)?;
+                self.env.set(name, value);
+                Ok(Value::None)
+            }
+            Expr::IndexAssign {
+                target,
+                index,
+                value,
+            } => {
+                let value = self.eval(value)?;
+                self.assign_index(target, index, value)?;
+                Ok(Value::None)
+            }
+            Expr::Let { name, value } => {
+                let value = self.eval(value)?;
+                self.env.declare(name, value);
+                Ok(Value::None)
+            }
+            Expr::BinaryOp { left, op, right } => self.eval_binary(left, op, right),
+            Expr::UnaryOp { op, expr } => {
+                let value = self.eval(expr)?;
+                match (op, value) {
+                    (UnaryOp::Not, value) => Ok(Value::Boolean(!value.is_truthy())),
+                    (UnaryOp::Neg, Value::Int(x)) => {
+                        x.checked_neg().map(Value::Int).ok_or_else(|| {
+                            Unwind::error("overflow", format!("integer overflow negating {x}"))
+                        })
+                    }
+                    (UnaryOp::Neg, Value::Float(x)) => Ok(Value::Float(-x)),
+                    (UnaryOp::Neg, value) => Err(Unwind::error(
+                        "type",
+                        format!("cannot negate a {}", value.type_name()),
+                    )),
+                }
+            }
+            Expr::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if self.eval_checked(cond)?.is_truthy() {
+                    self.eval(then_branch)
+                } else {
+                    self.eval(else_branch)
+                }
+            }
+            Expr::While { cond, body } => {
+                while self.eval_checked(cond)?.is_truthy() {
+                    self.check_signals()?;
+                    self.eval(body)?;
+                }
+                Ok(Value::None)
+            }
+            Expr::Destructure { pattern, value } => {
+                let value = self.eval(value)?;
+                self.destructure(pattern, &value, Bind::Assign)?;
+                Ok(Value::None)
+            }
+            Expr::For {
+                pattern,
+                iterable,
+                body,
+            } => {
+                for item in iterate(self.eval(iterable)?)? {
+                    self.check_signals()?;
+                    // each pass gets its own binding, so closures keep the value they saw
+                    self.env.push_scope();
+                    let result = self
+                        .destructure(pattern, &item, Bind::Declare)
+                        .and_then(|_| self.eval(body));
+                    self.env.pop_scope();
+                    result?;
+                }
+                Ok(Value::None)
+            }
+            Expr::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let start = self.eval_int(start, "range start")?;
+                let end = self.eval_int(end, "range end")?;
+                let step = match step {
+                    Some(step) => self.eval_int(step, "range step")?,
+                    None => 1,
+                };
+                if step == 0 {
+                    return Err(Unwind::error("value", "range step cannot be zero"));
+                }
+                Ok(Value::Range(Range {
+                    start,
+                    end,
+                    step,
+                    inclusive: *inclusive,
+                }))
+            }
+            Expr::Block(exprs) => {
+                self.env.push_scope();
+                let result = exprs
+                    .iter()
+                    .try_fold(Value::None, |_, expr| self.eval_statement(expr));
+                self.env.pop_scope();
+                result
+            }
+            Expr::Call { callee, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match callee.as_ref() {
+                    Expr::Identifier(name) => self.execute(name, args),
+                    callee => match self.eval(callee)? {
+                        Value::Function(function) => self.call_function(&function, args),
+                        other => Err(Unwind::error(
+                            "type",
+                            format!("a {} is not callable", other.type_name()),
+                        )),
+                    },
+                }
+            }
+            Expr::FuncDef { name, params, body } => {
+                let function = Function {
+                    name: Some(name.clone()),
+                    params: params.clone(),
+                    body: Rc::new(body.as_ref().clone()),
+                    captured: self.env.capture(),
+                    script: self.script.clone(),
+                };
+                self.env.define_function(name, Rc::new(function));
+                Ok(Value::None)
+            }
+            Expr::Lambda { params, body } => Ok(Value::Function(Rc::new(Function {
+                name: None,
+                params: params.clone(),
+                body: body.clone(),
+                captured: self.env.capture(),
+                script: self.script.clone(),
+            }))),
+            Expr::List(items) => Ok(Value::List(
+                items
+                    .iter()
+                    .map(|item| self.eval(item))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expr::Map(entries) => Ok(Value::Map(
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), self.eval(value)?)))
+                    .collect::<Result<_, Unwind>>()?,
+            )),
+            Expr::Index { target, index } => {
+                let target = self.eval(target)?;
+                let index = self.eval(index)?;
+                index_value(target, index)
+            }
+            Expr::AndThen { left, right } => {
+                let left = self.eval_checked(left)?;
+                if left.is_truthy() {
+                    self.eval_statement(right)
+                } else {
+                    Ok(left)
+                }
+            }
+            Expr::OrElse { left, right } => {
+                let left = self.eval_checked(left)?;
+                if left.is_truthy() {
+                    Ok(left)
+                } else {
+                    self.eval_statement(right)
+                }
+            }
+            Expr::Pipe { .. } => self.run_pipeline(expr),
+            Expr::Redirect { expr, redirects } => self.run_redirect(expr, redirects),
+            Expr::Return(value) => {
+                let value = self.eval(value)?;
+                Err(Unwind::Return(value))
+            }
+            Expr::CommandCall { command, args } => self.run_command(command, args),
+            Expr::Match { subject, arms } => self.eval_match(subject, arms),
+            Expr::Statement { .. } => self.eval_statement(expr),
+            Expr::Try {
+                body,
+                binding,
+                handler,
+                finally,
+            } => self.eval_try(
+                body,
+                binding.as_ref(),
+                handler.as_deref(),
+                finally.as_deref(),
+            ),
+            Expr::Background(expr) => self.run_background(expr),
+            Expr::Subshell(body) => self.run_subshell(body),
+            Expr::ProcessSubstitution { body, output } => self.run_substitution(body, *output),
+            Expr::Throw(value) => Err(match self.eval(value)? {
+                Value::Error(err) => Unwind::Error((*err).clone()),
+                other => Unwind::error("error", other.to_string()),
+            }),
+        }
+    }
+    // variables, then named functions as values
+    // `xs[i][j] = value`: reads `xs`, sets the item at the end of the chain of indexes and
+    // writes the variable back
+    fn assign_index(&mut self, target: &Expr, index: &Expr, value: Value) -> Result<(), Unwind> {
+        let mut path = vec![self.eval(index)?];
+        let mut target = target;
+        while let Expr::Index {
+            target: inner,
+            index,
+        } = target
+        {
+            path.push(self.eval(index)?);
+            target = inner;
+        }
+        let Expr::Identifier(name) = target else {
+            return Err(Unwind::error("syntax", "invalid assignment target"));
+        };
+        path.reverse();
+        let updated = set_index(self.lookup(name)?, &path, value)?;
+        self.env.set(name, updated);
+        Ok(())
+    }
+    pub(super) fn lookup(&self, name: &str) -> EvalResult {
+        if let Some(value) = self.env.get(name) {
+            return Ok(value);
+        }
+        match self.env.get_function(name) {
+            Some(function) => Ok(Value::Function(function)),
+            None if self.env.options.nounset => {
+                Err(Unwind::error("name", format!("undefined variable: {name}")))
+            }
+            None => Ok(Value::None),
+        }
+    }
+    fn eval_int(&mut self, expr: &Expr, what: &str) -> Result<i64, Unwind> {
+        match self.eval(expr)? {
+            Value::Int(x) => Ok(x),
+            other => Err(Unwind::error(
+                "type",
+                format!("{what} must be an int, got a {}", other.type_name()),
+            )),
+        }
+    }
+    // expands `$name`, `${name}` and `$?` inside strings and shell words
+    pub(super) fn interpolate(&self, text: &str) -> Result<String, Unwind> {
+        if !text.contains('$') {
+            return Ok(text.to_string());
+        }
+        let mut result = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if ch != '$' {
+                result.push(ch);
+                continue;
+            }
+            if chars.next_if_eq(&'?').is_some() {
+                result.push_str(&self.env.last_status.to_string());
+                continue;
+            }
+            let braced = chars.next_if_eq(&'{').is_some();
+            let mut name = String::new();
+            while let Some(next) = chars.next_if(|next| next.is_alphanumeric() || *next == '_') {
+                name.push(next);
+            }
+            if braced && chars.next_if_eq(&'}').is_none() {
+                return Err(Unwind::error(
+                    "syntax",
+                    format!("unterminated ${{ in \"{text}\""),
+                ));
+            }
+            // names never start with a digit, so `$1` is left for regex replacements
+            if name.is_empty() || name.starts_with(|ch: char| ch.is_ascii_digit()) {
+                match braced {
+                    true => result.push_str(&format!("${{{name}}}")),
+                    false => result.push_str(&format!("${name}")),
+                }
+                continue;
+            }
+            result.push_str(&self.lookup(&name)?.to_string());
+        }
+        Ok(result)
+    }
+    fn eval_binary(&mut self, left: &Expr, op: &BinaryOp, right: &Expr) -> EvalResult {
+        match op {
+            BinaryOp::And => {
+                let left = self.eval(left)?.is_truthy();
+                return Ok(Value::Boolean(left && self.eval(right)?.is_truthy()));
+            }
+            BinaryOp::Or => {
+                let left = self.eval(left)?.is_truthy();
+                return Ok(Value::Boolean(left || self.eval(right)?.is_truthy()));
+            }
+            _ => {}
+        }
+        let left = self.eval(left)?;
+        let right = self.eval(right)?;
+        match (op, &left, &right) {
+            (BinaryOp::Eq, _, _) => Ok(Value::Boolean(numeric_eq(&left, &right))),
+            (BinaryOp::Neq, _, _) => Ok(Value::Boolean(!numeric_eq(&left, &right))),
+            (BinaryOp::Matches, Value::String(text), Value::String(pattern)) => {
+                self.match_regex(text, pattern)
+            }
+            (BinaryOp::In, _, _) => contains(&right, &left).map(Value::Boolean),
+            (BinaryOp::Add, Value::String(_), _) | (BinaryOp::Add, _, Value::String(_)) => {
+                Ok(Value::String(format!("{left}{right}")))
+            }
+            (
+                BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::FloorDiv
+                | BinaryOp::Mod
+                | BinaryOp::Pow
+                | BinaryOp::BitAnd
+                | BinaryOp::BitOr
+                | BinaryOp::BitXor
+                | BinaryOp::Shl
+                | BinaryOp::Shr,
+                Value::Int(x),
+                Value::Int(y),
+            ) => int_arithmetic(op, *x, *y),
+            (
+                BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::FloorDiv
+                | BinaryOp::Mod
+                | BinaryOp::Pow,
+                Value::Int(_) | Value::Float(_),
+                Value::Int(_) | Value::Float(_),
+            ) => float_arithmetic(op, left.as_float().unwrap(), right.as_float().unwrap()),
+            (BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge, _, _) => {
+                let Some(ordering) = left.compare(&right) else {
+                    return Err(Unwind::error(
+                        "type",
+                        format!(
+                            "cannot compare {} and {}",
+                            left.type_name(),
+                            right.type_name()
+                        ),
+                    ));
+                };
+                Ok(Value::Boolean(match op {
+                    BinaryOp::Lt => ordering.is_lt(),
+                    BinaryOp::Le => ordering.is_le(),
+                    BinaryOp::Gt => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                }))
+            }
+            _ => Err(Unwind::error(
+                "type",
+                format!(
+                    "unsupported operands for {}: {} and {}",
+                    op,
+                    left.type_name(),
+                    right.type_name()
+                ),
+            )),
+        }
+    }
+    // the statement being run, for error spans and call sites
+    pub(super) fn location(&self) -> Span {
+        Span {
+            file: self.script.clone(),
+            line: self.line,
+        }
+    }
+    fn eval_try(
+        &mut self,
+        body: &Expr,
+        binding: Option<&Pattern>,
+        handler: Option<&Expr>,
+        finally: Option<&Expr>,
+    ) -> EvalResult {
+        // failing commands throw inside `try`, even in a condition further out
+        let conditions = std::mem::take(&mut self.conditions);
+        self.trying += 1;
+        let mut result = self.eval(body);
+        self.trying -= 1;
+        self.conditions = conditions;
+        if let (Err(Unwind::Error(err)), Some(handler)) = (&result, handler) {
+            let err = Value::Error(Rc::new(err.clone()));
+            self.env.push_scope();
+            result = match binding {
+                Some(binding) => self.destructure(binding, &err, Bind::Declare),
+                None => Ok(()),
+            }
+            .and_then(|_| self.eval(handler));
+            self.env.pop_scope();
+        }
+        if let Some(finally) = finally {
+            self.eval(finally)?;
+        }
+        result
+    }
+    pub(super) fn call_function(&mut self, function: &Function, args: Vec<Value>) -> EvalResult {
+        let name = function.name.as_deref().unwrap_or("lambda");
+        if args.len() > function.params.len() {
+            return Err(Unwind::error(
+                "type",
+                format!(
+                    "{name} expects {} arguments, got {}",
+                    function.params.len(),
+                    args.len()
+                ),
+            ));
+        }
+        let mut args = args.into_iter();
+        let call_site = self.location();
+        self.env.push_frame(
+            Some(name.to_string()),
+            function.captured.clone(),
+            call_site.clone(),
+        );
+        // the body's statements belong to the file that defined it
+        self.script = function.script.clone();
+        let result = function
+            .params
+            .iter()
+            .try_for_each(|param| {
+                let arg = args.next().unwrap_or(Value::None);
+                self.destructure(param, &arg, Bind::Declare)
+            })
+            .and_then(|_| self.eval(&function.body));
+        self.env.pop_frame();
+        self.line = call_site.line;
+        self.script = call_site.file.clone();
+        match result {
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(mut err)) => {
+                err.trace.push((name.to_string(), call_site));
+                Err(Unwind::Error(err))
+            }
+            other => other,
+        }
+    }
+}
+
+// the values a `for` loop visits: list items, range numbers, string characters or map keys
+fn iterate(value: Value) -> Result<Box<dyn Iterator<Item = Value>>, Unwind> {
+    match value {
+        Value::List(items) => Ok(Box::new(items.into_iter())),
+        Value::Range(range) => Ok(Box::new(range.iter().map(Value::Int))),
+        Value::String(text) => {
+            let chars: Vec<_> = text
+                .chars()
+                .map(|ch| Value::String(ch.to_string()))
+                .collect();
+            Ok(Box::new(chars.into_iter()))
+        }
+        Value::Map(entries) => Ok(Box::new(entries.into_keys().map(Value::String))),
+        other => Err(Unwind::error(
+            "type",
+            format!("cannot iterate over a {}", other.type_name()),
+        )),
+    }
+}
+
+// `item in container`
+fn contains(container: &Value, item: &Value) -> Result<bool, Unwind> {
+    match (container, item) {
+        (Value::List(items), item) => Ok(items.iter().any(|x| numeric_eq(x, item))),
+        (Value::Range(range), Value::Int(x)) => Ok(range.contains(*x)),
+        (Value::Range(_), _) => Ok(false),
+        (Value::String(text), Value::String(part)) => Ok(text.contains(part.as_str())),
+        (Value::Map(entries), Value::String(key)) => Ok(entries.contains_key(key)),
+        (container, item) => Err(Unwind::error(
+            "type",
+            format!(
+                "cannot look for a {} in a {}",
+                item.type_name(),
+                container.type_name()
+            ),
+        )),
+    }
+}
+
+fn index_value(target: Value, index: Value) -> EvalResult {
+    match (target, index) {
+        (Value::Error(err), Value::String(key)) => Ok(err.field(&key).unwrap_or(Value::None)),
+        (Value::List(items), Value::Range(range)) => {
+            let items = slice(range, items.len())?.map(|i| items[i].clone());
+            Ok(Value::List(items.collect()))
+        }
+        (Value::String(text), Value::Range(range)) => {
+            let chars: Vec<char> = text.chars().collect();
+            let chars = slice(range, chars.len())?.map(|i| chars[i]);
+            Ok(Value::String(chars.collect()))
+        }
+        (Value::Range(range), Value::Int(i)) => {
+            let item = range.get(i).map(Value::Int);
+            item.ok_or_else(|| Unwind::error("index", format!("range index {i} out of range")))
+        }
+        (Value::List(items), Value::Int(i)) => {
+            let item = position(i, items.len()).and_then(|i| items.into_iter().nth(i));
+            item.ok_or_else(|| Unwind::error("index", format!("list index {i} out of range")))
+        }
+        (Value::String(text), Value::Int(i)) => {
+            let chars: Vec<char> = text.chars().collect();
+            let ch = position(i, chars.len()).map(|i| Value::String(chars[i].to_string()));
+            ch.ok_or_else(|| Unwind::error("index", format!("string index {i} out of range")))
+        }
+        (Value::Map(mut entries), key) => {
+            Ok(entries.remove(&key.to_string()).unwrap_or(Value::None))
+        }
+        (target, index) => Err(Unwind::error(
+            "type",
+            format!(
+                "cannot index a {} with a {}",
+                target.type_name(),
+                index.type_name()
+            ),
+        )),
+    }
+}
+
+// `target` with the item at the end of `path` replaced by `value`
+fn set_index(target: Value, path: &[Value], value: Value) -> EvalResult {
+    let Some((index, rest)) = path.split_first() else {
+        return Ok(value);
+    };
+    match (target, index) {
+        (Value::List(mut items), Value::Int(i)) => {
+            let Some(i) = position(*i, items.len()) else {
+                return Err(Unwind::error(
+                    "index",
+                    format!("list index {i} out of range"),
+                ));
+            };
+            let item = std::mem::replace(&mut items[i], Value::None);
+            items[i] = set_index(item, rest, value)?;
+            Ok(Value::List(items))
+        }
+        (Value::Map(mut entries), key) => {
+            let key = key.to_string();
+            let item = entries.remove(&key).unwrap_or(Value::None);
+            entries.insert(key, set_index(item, rest, value)?);
+            Ok(Value::Map(entries))
+        }
+        (target, index) => Err(Unwind::error(
+            "type",
+            format!(
+                "cannot assign to a {} index of a {}",
+                index.type_name(),
+                target.type_name()
+            ),
+        )),
+    }
+}
+
+// the positions `xs[1..3]` selects: bounds count from the end when negative and are clamped
+fn slice(range: Range, len: usize) -> Result<impl Iterator<Item = usize>, Unwind> {
+    if range.step < 0 {
+        return Err(Unwind::error("value", "slice step must be positive"));
+    }
+    let len = len as i64;
+    let bound = |index: i64| {
+        if index < 0 {
+            (index + len).max(0)
+        } else {
+            index.min(len)
+        }
+    };
+    let start = bound(range.start);
+    let mut end = bound(range.end);
+    if range.inclusive {
+        end = (end + 1).min(len);
+    }
+    Ok((start..end.max(start))
+        .step_by(range.step as usize)
+        .map(|i| i as usize))
+}
+
+// an index into a sequence of `len`, counting from the end when negative
+fn position(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+// `1 == 1.0`: numbers compare by value across int and float
+pub(super) fn numeric_eq(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            left.compare(right) == Some(std::cmp::Ordering::Equal)
+        }
+        _ => left == right,
+    }
+}
+
+// int arithmetic stays exact: overflow is an error and `//` floors, while `/` is true
+// division and always gives a float
+fn int_arithmetic(op: &BinaryOp, x: i64, y: i64) -> EvalResult {
+    if matches!(op, BinaryOp::Div | BinaryOp::FloorDiv | BinaryOp::Mod) && y == 0 {
+        return Err(Unwind::error("zero_division", "division by zero"));
+    }
+    let result = match op {
+        BinaryOp::Add => x.checked_add(y),
+        BinaryOp::Sub => x.checked_sub(y),
+        BinaryOp::Mul => x.checked_mul(y),
+        BinaryOp::Div => return Ok(Value::Float(x as f64 / y as f64)),
+        BinaryOp::FloorDiv => x.checked_div(y).map(|q| {
+            if x % y != 0 && (x < 0) != (y < 0) {
+                q - 1
+            } else {
+                q
+            }
+        }),
+        // the remainder takes the divisor's sign, to go with `//` flooring
+        BinaryOp::Mod => Some(match x.wrapping_rem(y) {
+            r if r != 0 && (r < 0) != (y < 0) => r + y,
+            r => r,
+        }),
+        // a negative power has no int result
+        BinaryOp::Pow if y < 0 => return Ok(Value::Float((x as f64).powf(y as f64))),
+        BinaryOp::Pow => u32::try_from(y).ok().and_then(|y| x.checked_pow(y)),
+        BinaryOp::BitAnd => Some(x & y),
+        BinaryOp::BitOr => Some(x | y),
+        BinaryOp::BitXor => Some(x ^ y),
+        BinaryOp::Shl | BinaryOp::Shr if !(0..64).contains(&y) => {
+            return Err(Unwind::error(
+                "value",
+                format!("shift amount {y} out of range"),
+            ));
+        }
+        BinaryOp::Shl => Some(x << y).filter(|shifted| shifted >> y == x),
+        _ => Some(x >> y),
+    };
+    result
+        .map(Value::Int)
+        .ok_or_else(|| Unwind::error("overflow", format!("integer overflow in {x} {op} {y}")))
+}
+
+fn float_arithmetic(op: &BinaryOp, x: f64, y: f64) -> EvalResult {
+    if matches!(op, BinaryOp::Div | BinaryOp::FloorDiv | BinaryOp::Mod) && y == 0.0 {
+        return Err(Unwind::error("zero_division", "division by zero"));
+    }
+    Ok(Value::Float(match op {
+        BinaryOp::Add => x + y,
+        BinaryOp::Sub => x - y,
+        BinaryOp::Mul => x * y,
+        BinaryOp::Div => x / y,
+        BinaryOp::FloorDiv => (x / y).floor(),
+        BinaryOp::Pow => x.powf(y),
+        _ => match x % y {
+            r if r != 0.0 && (r < 0.0) != (y < 0.0) => r + y,
+            r => r,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(op: BinaryOp, x: i64, y: i64) -> i64 {
+        match int_arithmetic(&op, x, y) {
+            Ok(Value::Int(result)) => result,
+            other => panic!("{x} {op} {y} gave {other:?}"),
+        }
+    }
+
+    fn float(op: BinaryOp, x: f64, y: f64) -> f64 {
+        match float_arithmetic(&op, x, y) {
+            Ok(Value::Float(result)) => result,
+            other => panic!("{x} {op} {y} gave {other:?}"),
+        }
+    }
+
+    #[test]
+    fn remainder_follows_floor_division() {
+        for (x, y) in [(7, 3), (-7, 3), (7, -3), (-7, -3), (6, -3)] {
+            let (q, r) = (int(BinaryOp::FloorDiv, x, y), int(BinaryOp::Mod, x, y));
+            assert_eq!(q.wrapping_mul(y).wrapping_add(r), x, "{x} % {y}");
+        }
+        assert_eq!(int(BinaryOp::Mod, -7, 3), 2);
+        assert_eq!(int(BinaryOp::Mod, 7, -3), -2);
+        assert_eq!(int(BinaryOp::Mod, -6, 3), 0);
+        assert_eq!(int(BinaryOp::Mod, i64::MIN, -1), 0);
+        assert_eq!(float(BinaryOp::Mod, -7.5, 2.0), 0.5);
+        assert_eq!(float(BinaryOp::Mod, 7.5, -2.0), -0.5);
+        assert_eq!(float(BinaryOp::Mod, -7.5, -2.0), -1.5);
+    }
+}
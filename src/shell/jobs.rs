@@ -0,0 +1,576 @@
+use super::Shell;
+use super::ast::{Ast, Expr, RedirectKind, Redirection};
+use super::exec::{dup_above, io_error};
+use super::interpreter::{EvalResult, Unwind};
+use super::signals;
+use super::values::Value;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+
+// signals the interactive shell ignores so it can hand the terminal around; jobs get them
+// back at their defaults, along with the Ctrl-Z the shell forwards
+const JOB_CONTROL_SIGNALS: [libc::c_int; 2] = [libc::SIGTTIN, libc::SIGTTOU];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(i32), // the status of its last process
+}
+
+// a background or stopped pipeline, `%1` to `fg`, `bg` and `wait`
+pub struct Job {
+    pub id: usize, // 0 until it goes in the job table
+    pub pgid: i32,
+    pub processes: Vec<(i32, Option<i32>)>, // each pid and its status once it has exited
+    pub command: String,
+    pub state: JobState,
+    pub changed: bool, // finished or stopped since `jobs` or the prompt last said so
+}
+
+// what `waitpid` reported for one process
+enum Event {
+    Exited(i32),
+    Killed(i32), // by this signal
+    Stopped,
+    Continued,
+}
+
+// `None` when there is nothing to report under WNOHANG, or no such child
+fn wait_pid(pid: i32, flags: libc::c_int) -> Option<Event> {
+    let mut status = 0;
+    loop {
+        let result = unsafe { libc::waitpid(pid, &mut status, flags) };
+        if result > 0 {
+            break;
+        }
+        if result == 0 || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return None;
+        }
+    }
+    Some(if libc::WIFSTOPPED(status) {
+        Event::Stopped
+    } else if libc::WIFCONTINUED(status) {
+        Event::Continued
+    } else if libc::WIFSIGNALED(status) {
+        Event::Killed(libc::WTERMSIG(status))
+    } else {
+        Event::Exited(libc::WEXITSTATUS(status))
+    })
+}
+
+// forks after flushing what's buffered, so the child doesn't write it again
+fn fork() -> Result<i32, Unwind> {
+    io::stdout().flush().ok();
+    io::stderr().flush().ok();
+    match unsafe { libc::fork() } {
+        pid if pid < 0 => Err(io_error(io::Error::last_os_error())),
+        pid => Ok(pid),
+    }
+}
+// puts a forked job's signals back the way a program expects them
+pub(super) fn reset_signals() {
+    for signal in [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP]
+        .into_iter()
+        .chain(JOB_CONTROL_SIGNALS)
+    {
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+// roughly the source of a job's command, for `jobs` and notifications
+pub(super) fn describe(expr: &Expr) -> String {
+    match expr {
+        Expr::Statement { expr, .. } => describe(expr),
+        Expr::Identifier(word) | Expr::ShellWord(word) => word.clone(),
+        Expr::Literal(Value::String(text)) => format!("{text:?}"),
+        Expr::Literal(value) => value.to_string(),
+        Expr::CommandCall { command, args } => std::iter::once(&**command)
+            .chain(args)
+            .map(describe)
+            .collect::<Vec<_>>()
+            .join(" "),
+        Expr::Pipe { left, right } => format!("{} | {}", describe(left), describe(right)),
+        Expr::AndThen { left, right } => format!("{} && {}", describe(left), describe(right)),
+        Expr::OrElse { left, right } => format!("{} || {}", describe(left), describe(right)),
+        Expr::Redirect { expr, redirects } => {
+            let mut text = describe(expr);
+            for Redirection { fd, kind } in redirects {
+                let default = match kind {
+                    RedirectKind::In(_)
+                    | RedirectKind::HereString(_)
+                    | RedirectKind::HereDoc(_)
+                    | RedirectKind::RawHereDoc(_) => 0,
+                    _ => 1,
+                };
+                let fd = match fd == &default {
+                    true => String::new(),
+                    false => fd.to_string(),
+                };
+                let redirect = match kind {
+                    RedirectKind::In(target) => format!("<- {}", describe(target)),
+                    RedirectKind::Out(target) => format!("-> {}", describe(target)),
+                    RedirectKind::Append(target) => format!(">> {}", describe(target)),
+                    RedirectKind::HereString(value) => format!("<<< {}", describe(value)),
+                    RedirectKind::HereDoc(_) | RedirectKind::RawHereDoc(_) => "<< ...".into(),
+                    RedirectKind::Dup(source) => format!("->&{source}"),
+                    RedirectKind::Close => "->&-".into(),
+                };
+                text.push_str(&format!(" {fd}{redirect}"));
+            }
+            text
+        }
+        Expr::Call { callee, .. } => format!("{}(...)", describe(callee)),
+        _ => "{ ... }".to_string(),
+    }
+}
+
+impl Job {
+    pub fn new(pgid: i32, pids: Vec<i32>, command: String) -> Self {
+        Job {
+            id: 0,
+            pgid,
+            processes: pids.into_iter().map(|pid| (pid, None)).collect(),
+            command,
+            state: JobState::Running,
+            changed: false,
+        }
+    }
+    fn status(&self) -> i32 {
+        self.processes
+            .last()
+            .and_then(|(_, status)| *status)
+            .unwrap_or(0)
+    }
+    fn update(&mut self, pid: i32, event: Event) {
+        match event {
+            Event::Killed(signal) => self.update(pid, Event::Exited(128 + signal)),
+            Event::Exited(status) => {
+                if let Some(process) = self.processes.iter_mut().find(|(id, _)| *id == pid) {
+                    process.1 = Some(status);
+                }
+                if self.processes.iter().all(|(_, status)| status.is_some()) {
+                    self.state = JobState::Done(self.status());
+                    self.changed = true;
+                }
+            }
+            Event::Stopped if self.state != JobState::Stopped => {
+                self.state = JobState::Stopped;
+                self.changed = true;
+            }
+            Event::Stopped => {}
+            Event::Continued => self.state = JobState::Running,
+        }
+    }
+}
+
+impl Shell {
+    // the interactive shell leads its own process group and owns the terminal, handing it to
+    // each foreground job in turn
+    pub(super) fn enable_job_control(&mut self) {
+        unsafe {
+            for signal in JOB_CONTROL_SIGNALS {
+                libc::signal(signal, libc::SIG_IGN);
+            }
+            signals::catch(&[libc::SIGTSTP]);
+            libc::setpgid(0, 0);
+            self.shell_pgid = libc::getpgrp();
+            libc::tcsetpgrp(libc::STDIN_FILENO, self.shell_pgid);
+        }
+        self.job_control = true;
+    }
+    // the terminal, and the signals sent to the shell, go to `pgid`
+    fn give_terminal(&self, pgid: i32) {
+        if self.job_control {
+            unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid) };
+            signals::set_foreground(if pgid == self.shell_pgid { 0 } else { pgid });
+        }
+    }
+    fn add_job(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.env.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        let at = self.env.jobs.partition_point(|other| other.id < id);
+        self.env.jobs.insert(at, job);
+        id
+    }
+    fn print_job(&self, job: &Job) {
+        let current = self.env.jobs.last().map(|last| last.id);
+        let previous = self.env.jobs.iter().rev().nth(1).map(|job| job.id);
+        let marker = match Some(job.id) {
+            id if id == current => '+',
+            id if id == previous => '-',
+            _ => ' ',
+        };
+        let state = match job.state {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => "Stopped".to_string(),
+            JobState::Done(0) => "Done".to_string(),
+            JobState::Done(status) => format!("Exit {status}"),
+        };
+        writeln!(
+            self.stdout(),
+            "[{}]{marker}  {state:<10}{}",
+            job.id,
+            job.command
+        )
+        .ok();
+    }
+    // waits for a job holding the terminal; its statuses in order once every process exits,
+    // or `None` after Ctrl-Z stops it and it moves to the job table
+    pub(super) fn wait_foreground(&mut self, mut job: Job) -> Option<Vec<i32>> {
+        self.give_terminal(job.pgid);
+        let flags = if self.job_control { libc::WUNTRACED } else { 0 };
+        let mut stopped = false;
+        for i in 0..job.processes.len() {
+            let pid = job.processes[i].0;
+            while job.processes[i].1.is_none() {
+                match wait_pid(pid, flags) {
+                    Some(Event::Stopped) => {
+                        stopped = true;
+                        break;
+                    }
+                    // a job killed by Ctrl-C interrupts the script that ran it, too
+                    Some(Event::Killed(libc::SIGINT)) => {
+                        signals::interrupt();
+                        job.update(pid, Event::Killed(libc::SIGINT));
+                    }
+                    Some(Event::Killed(signal)) => {
+                        if signal != libc::SIGPIPE {
+                            self.report(&format!("{}: killed by signal {signal}", job.command));
+                        }
+                        job.update(pid, Event::Killed(signal));
+                    }
+                    Some(event) => job.update(pid, event),
+                    // already reaped elsewhere, so its status is lost
+                    None => job.processes[i].1 = Some(0),
+                }
+            }
+            if stopped {
+                break;
+            }
+        }
+        self.give_terminal(self.shell_pgid);
+        if !stopped {
+            return Some(job.processes.iter().map(|(_, s)| s.unwrap_or(0)).collect());
+        }
+        job.state = JobState::Stopped;
+        job.changed = false;
+        let id = self.add_job(job);
+        writeln!(self.stdout()).ok();
+        if let Some(job) = self.env.jobs.iter().find(|job| job.id == id) {
+            self.print_job(job);
+        }
+        None
+    }
+    // `cmd &`: a forked copy of the shell runs the statement in its own process group
+    pub(super) fn run_background(&mut self, expr: &Expr) -> EvalResult {
+        let pid = fork()?;
+        if pid == 0 {
+            let status = self.run_job(expr);
+            io::stdout().flush().ok();
+            std::process::exit(status);
+        }
+        if self.job_control {
+            unsafe { libc::setpgid(pid, pid) };
+        }
+        let id = self.add_job(Job::new(pid, vec![pid], describe(expr)));
+        if self.job_control {
+            writeln!(self.stderr(), "[{id}] {pid}").ok();
+        }
+        self.env.last_status = 0;
+        Ok(Value::Status(0))
+    }
+    // a pipeline stage run by the shell itself, in a forked copy that joins the pipeline's
+    // process group (or starts it when `pgid` is 0); returns the copy's pid. `next` is the
+    // reading end of its output, which the copy closes so it sees the reader go away
+    pub(super) fn fork_stage(
+        &mut self,
+        stage: &Expr,
+        pgid: i32,
+        next: &mut Option<OwnedFd>,
+    ) -> Result<i32, Unwind> {
+        let pid = fork()?;
+        if pid == 0 {
+            drop(next.take());
+            if self.job_control {
+                unsafe { libc::setpgid(0, pgid) };
+                reset_signals();
+            }
+            // a reader that stops early, like `head`, ends the stage the way it would a program
+            unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+            self.job_control = false;
+            self.env.jobs.clear();
+            self.env.traps.clear();
+            self.substitutions.clear();
+            let status = match self.eval_statement(stage) {
+                Ok(Value::Status(code)) => code,
+                Ok(value) => {
+                    self.emit(&value);
+                    0
+                }
+                Err(Unwind::Exit(code)) => code,
+                Err(Unwind::Error(err)) => {
+                    self.report_error(&err);
+                    err.status
+                }
+                Err(Unwind::Return(_)) => self.env.last_status,
+            };
+            io::stdout().flush().ok();
+            std::process::exit(status);
+        }
+        if self.job_control {
+            unsafe { libc::setpgid(pid, if pgid == 0 { pid } else { pgid }) };
+        }
+        Ok(pid)
+    }
+    // `( ... )` runs in a forked copy of the shell, so its variables, functions, working
+    // directory and `exit` stay there; the value is its exit status
+    pub(super) fn run_subshell(&mut self, body: &[Expr]) -> EvalResult {
+        let pid = fork()?;
+        if pid == 0 {
+            self.exit_forked(body);
+        }
+        if self.job_control {
+            unsafe { libc::setpgid(pid, pid) };
+        }
+        let command = format!("( {} )", describe(&body[0]));
+        let status = match self.wait_foreground(Job::new(pid, vec![pid], command)) {
+            Some(statuses) => statuses[0],
+            None => 128 + libc::SIGTSTP,
+        };
+        self.check_status("subshell", Value::Status(status))
+    }
+    // `<(cmd)` or `>(cmd)`: a forked copy of the shell runs `body` writing to, or reading
+    // from, a pipe whose other end is passed on as a `/dev/fd/N` path
+    pub(super) fn run_substitution(&mut self, body: &[Expr], output: bool) -> EvalResult {
+        let (reader, writer) = io::pipe().map_err(io_error)?;
+        let (near, far) = match output {
+            true => (OwnedFd::from(writer), OwnedFd::from(reader)),
+            false => (OwnedFd::from(reader), OwnedFd::from(writer)),
+        };
+        let pid = fork()?;
+        if pid == 0 {
+            drop(near);
+            match output {
+                true => self.io.stdin = Some(far),
+                false => self.io.stdout = Some(far),
+            }
+            self.exit_forked(body);
+        }
+        drop(far);
+        // the command inherits it across exec, clear of any descriptors it redirects
+        let near = dup_above(near.as_raw_fd(), false).map_err(io_error)?;
+        let path = format!("/dev/fd/{}", near.as_raw_fd());
+        self.substitutions.push((pid, near));
+        Ok(Value::String(path))
+    }
+    // closes the pipes of process substitutions from `depth` on and waits for their shells,
+    // so a `>(cmd)` is done with its input by the time the statement that made it ends
+    pub(super) fn finish_substitutions(&mut self, depth: usize) {
+        let pids: Vec<_> = self
+            .substitutions
+            .drain(depth..)
+            .map(|(pid, _)| pid)
+            .collect();
+        for pid in pids {
+            wait_pid(pid, 0);
+        }
+    }
+    // the forked side of `run_subshell` and `run_substitution`, which never returns
+    fn exit_forked(&mut self, body: &[Expr]) -> ! {
+        if self.job_control {
+            unsafe { libc::setpgid(0, 0) };
+            reset_signals();
+        }
+        self.job_control = false;
+        self.env.jobs.clear();
+        self.env.traps.clear();
+        self.substitutions.clear();
+        let status = match self.evaluate(&Ast {
+            body: body.to_vec(),
+        }) {
+            Ok(Value::Status(code)) => code,
+            Ok(value) => {
+                self.emit(&value);
+                self.env.last_status
+            }
+            Err(Unwind::Exit(code)) => code,
+            Err(Unwind::Error(err)) => {
+                self.report_error(&err);
+                err.status
+            }
+            Err(Unwind::Return(_)) => self.env.last_status,
+        };
+        io::stdout().flush().ok();
+        std::process::exit(status);
+    }
+    // the forked side of `run_background`, returning the status to exit with
+    fn run_job(&mut self, expr: &Expr) -> i32 {
+        if self.job_control {
+            unsafe { libc::setpgid(0, 0) };
+            reset_signals();
+        } else if self.io.stdin.is_none() {
+            // without job control a background job can't take the terminal, so it reads nothing
+            self.io.stdin = File::open("/dev/null").ok().map(OwnedFd::from);
+        }
+        self.job_control = false;
+        self.env.jobs.clear();
+        self.env.traps.clear();
+        match self.eval_statement(expr) {
+            Ok(Value::Status(code)) => code,
+            Ok(_) => 0,
+            Err(Unwind::Exit(code)) => code,
+            Err(Unwind::Error(err)) => {
+                self.report_error(&err);
+                err.status
+            }
+            Err(Unwind::Return(_)) => self.env.last_status,
+        }
+    }
+    // collects whatever jobs have finished, stopped or continued, without blocking
+    fn reap_jobs(&mut self) {
+        let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+        for job in &mut self.env.jobs {
+            let pending: Vec<_> = job
+                .processes
+                .iter()
+                .filter(|(_, status)| status.is_none())
+                .map(|(pid, _)| *pid)
+                .collect();
+            for pid in pending {
+                while let Some(event) = wait_pid(pid, flags) {
+                    let exited = matches!(event, Event::Exited(_));
+                    job.update(pid, event);
+                    if exited {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    // before each prompt, says which jobs finished or stopped since the last one
+    pub(super) fn notify_jobs(&mut self) {
+        self.reap_jobs();
+        for job in self.env.jobs.iter().filter(|job| job.changed) {
+            self.print_job(job);
+        }
+        self.forget_jobs();
+    }
+    // once a job's end has been reported it leaves the table
+    fn forget_jobs(&mut self) {
+        for job in &mut self.env.jobs {
+            job.changed = false;
+        }
+        self.env
+            .jobs
+            .retain(|job| !matches!(job.state, JobState::Done(_)));
+    }
+    // the index of a job from `%2`, `2`, `%+`/`%%` (the current job), `%-` or `%name`
+    fn find_job(&self, spec: Option<&str>) -> Result<usize, String> {
+        let jobs = &self.env.jobs;
+        let found = match spec.map(|spec| spec.strip_prefix('%').unwrap_or(spec)) {
+            None | Some("+" | "%" | "") => jobs.len().checked_sub(1),
+            Some("-") => jobs.len().checked_sub(2),
+            Some(id) if id.parse::<usize>().is_ok() => {
+                let id: usize = id.parse().unwrap();
+                jobs.iter().position(|job| job.id == id)
+            }
+            Some(name) => jobs.iter().position(|job| job.command.starts_with(name)),
+        };
+        found.ok_or_else(|| format!("{}: no such job", spec.unwrap_or("current")))
+    }
+    pub(super) fn jobs(&mut self) -> i32 {
+        self.reap_jobs();
+        for job in &self.env.jobs {
+            self.print_job(job);
+        }
+        self.forget_jobs();
+        0
+    }
+    pub(super) fn fg(&mut self, args: &[String]) -> i32 {
+        let index = match self.find_job(args.first().map(String::as_str)) {
+            Ok(index) => index,
+            Err(err) => {
+                self.report(&format!("fg: {err}"));
+                return 1;
+            }
+        };
+        let job = self.env.jobs.remove(index);
+        writeln!(self.stdout(), "{}", job.command).ok();
+        unsafe { libc::kill(-job.pgid, libc::SIGCONT) };
+        match self.wait_foreground(job) {
+            Some(statuses) => statuses.last().copied().unwrap_or(0),
+            None => 128 + libc::SIGTSTP,
+        }
+    }
+    pub(super) fn bg(&mut self, args: &[String]) -> i32 {
+        let index = match self.find_job(args.first().map(String::as_str)) {
+            Ok(index) => index,
+            Err(err) => {
+                self.report(&format!("bg: {err}"));
+                return 1;
+            }
+        };
+        let job = &mut self.env.jobs[index];
+        job.state = JobState::Running;
+        unsafe { libc::kill(-job.pgid, libc::SIGCONT) };
+        let line = format!("[{}] {} &", job.id, job.command);
+        writeln!(self.stdout(), "{line}").ok();
+        0
+    }
+    // `wait` for every job, or `wait %1 1234` for particular jobs and pids
+    pub(super) fn wait(&mut self, args: &[String]) -> i32 {
+        let mut jobs = Vec::new();
+        if args.is_empty() {
+            jobs = std::mem::take(&mut self.env.jobs);
+        }
+        for arg in args {
+            let pid = arg.parse::<i32>().ok().filter(|_| !arg.starts_with('%'));
+            let index = match pid {
+                Some(pid) => self
+                    .env
+                    .jobs
+                    .iter()
+                    .position(|job| job.processes.iter().any(|(id, _)| *id == pid)),
+                None => self.find_job(Some(arg)).ok(),
+            };
+            match (index, pid) {
+                (Some(index), _) => jobs.push(self.env.jobs.remove(index)),
+                // a process that isn't a job, like one started by a builtin
+                (None, Some(pid)) => jobs.push(Job::new(pid, vec![pid], arg.clone())),
+                (None, None) => {
+                    self.report(&format!("wait: {arg}: no such job"));
+                    return 127;
+                }
+            }
+        }
+        let mut status = 0;
+        for mut job in jobs {
+            // a stopped job won't finish until something continues it, so waiting for it ends
+            // there, as `fg` does, and it stays in the job table
+            for i in 0..job.processes.len() {
+                let pid = job.processes[i].0;
+                while job.processes[i].1.is_none() && job.state != JobState::Stopped {
+                    match wait_pid(pid, libc::WUNTRACED) {
+                        Some(event) => job.update(pid, event),
+                        None => job.processes[i].1 = Some(127),
+                    }
+                }
+            }
+            if job.state != JobState::Stopped {
+                status = job.status();
+                continue;
+            }
+            self.report(&format!("wait: {} is stopped", job.command));
+            status = 128 + libc::SIGTSTP;
+            if job.id != 0 {
+                job.changed = false;
+                self.add_job(job);
+            }
+        }
+        status
+    }
+}
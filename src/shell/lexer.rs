@@ -1,7 +1,23 @@
 use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
 
 use regex::Regex;
 
+// why the lexer gave up, and the line it was on
+#[derive(Debug)]
+pub struct LexError {
+    pub message: String,
+    pub line: usize,
+}
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl Error for LexError {}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Bool {
     True,
@@ -10,7 +26,8 @@ pub enum Bool {
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     // literal types
-    Number(f64),
+    Int(i64),
+    Float(f64),
     Bool(Bool),
     String(String),
     Identifier(String),
@@ -21,57 +38,489 @@ pub enum Token {
     While,
     Function,
     For,
+    Else,
+    Return,
+    Let,
+    Match,
+    In,
+    Try,
+    Catch,
+    Finally,
+    Throw,
 
     // grouping operators
-    Equals,            // =
-    OpenParen,         // (
-    CloseParen,        // )
-    OpenBrace,         // {
-    CloseBrace,        // }
-    OpenBracket,       // [
-    CloseBracket,      // ]
-    Add,               // +
-    Sub,               // -
-    Mul,               // *
-    Div,               // /
-    Mod,               // %
-    And,               // and
-    Or,                // or
-    AndThen,           // &&
-    OrElse,            // ||
-    Pipe,              // |
-    RedirectIn,        // <-
-    RedirectOut,       // ->
-    RedirectOutAppend, // >>
-    GreaterThan,       // >
-    GreaterThanEqual,  // >=
-    LessThan,          // <
-    LessThanEqual,     // <=
-    EqualTo,           // ==
-    NotEqualTo,        // !=
+    Equals,             // =
+    OpenParen,          // (
+    CloseParen,         // )
+    OpenBrace,          // {
+    CloseBrace,         // }
+    OpenBracket,        // [
+    CloseBracket,       // ]
+    Add,                // +
+    Sub,                // -
+    Mul,                // *
+    Div,                // /
+    Mod,                // %
+    Pow,                // **
+    FloorDiv,           // //
+    BitAnd,             // band
+    BitOr,              // bor
+    BitXor,             // bxor
+    ShiftLeft,          // shl
+    ShiftRight,         // shr
+    AddAssign,          // +=
+    SubAssign,          // -=
+    MulAssign,          // *=
+    DivAssign,          // /=
+    And,                // and
+    Or,                 // or
+    AndThen,            // &&
+    OrElse,             // ||
+    Pipe,               // |
+    Background,         // &
+    RedirectIn,         // <-
+    RedirectOut,        // ->
+    RedirectOutAppend,  // >>
+    FdRedirect(String), // 2->, &>>, 2->&1, 2->&-, 2->null and the like
+    ProcessIn,          // the `<` of <(cmd)
+    ProcessOut,         // the `>` of >(cmd)
+    HereString,         // <<<
+    HereDoc(String),    // <<EOF, with its body
+    RawHereDoc(String), // <<'EOF', whose body isn't interpolated
+    GreaterThan,        // >
+    GreaterThanEqual,   // >=
+    LessThan,           // <
+    LessThanEqual,      // <=
+    EqualTo,            // ==
+    NotEqualTo,         // !=
+    Matches,            // =~
+    DotDot,             // ..
+    DotDotEqual,        // ..=
 
     // unary
     Not, // not / !
 
     // other
     Comma,   // ,
+    Colon,   // :
+    Arrow,   // =>
     Newline, // \n
     Eof,     // end of file
 }
+fn is_binary_operator(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Add
+            | Token::Sub
+            | Token::Mul
+            | Token::Div
+            | Token::Mod
+            | Token::Pow
+            | Token::FloorDiv
+            | Token::BitAnd
+            | Token::BitOr
+            | Token::BitXor
+            | Token::ShiftLeft
+            | Token::ShiftRight
+            | Token::And
+            | Token::Or
+            | Token::GreaterThan
+            | Token::GreaterThanEqual
+            | Token::LessThan
+            | Token::LessThanEqual
+            | Token::EqualTo
+            | Token::NotEqualTo
+            | Token::Matches
+            | Token::DotDot
+            | Token::DotDotEqual
+            | Token::In
+    )
+}
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || "_'+-*/%=<>&|!.$?~".contains(ch)
+}
+fn ends_operand(mut rest: impl Iterator<Item = char>) -> bool {
+    matches!(
+        rest.find(|next| *next != ' ' && *next != '\t'),
+        None | Some('\n' | '#' | ')' | '}' | '|' | '&')
+    )
+}
+// whether `tokens` ends in a `-` that negates rather than subtracts, having no operand before it
+fn negates(tokens: &[Token]) -> bool {
+    let [.., before, Token::Sub] = tokens else {
+        return tokens == [Token::Sub];
+    };
+    !matches!(
+        before,
+        Token::Int(_)
+            | Token::Float(_)
+            | Token::Bool(_)
+            | Token::String(_)
+            | Token::Identifier(_)
+            | Token::ShellWord(_)
+            | Token::CloseParen
+            | Token::CloseBracket
+    )
+}
+// true when `rest` starts with a glob class like `[abc]`, closed before the word ends
+fn glob_class(mut rest: impl Iterator<Item = char>) -> bool {
+    rest.next() == Some('[')
+        && rest
+            .take_while(|next| !next.is_whitespace() && !"[,\"".contains(*next))
+            .enumerate()
+            .any(|(i, next)| next == ']' && i > 0)
+}
+// true when `rest` starts with an index like `[0]`, `[-1]`, `[i]` or a range like `[0..2]` that
+// ends the word, which after a name like `xs` indexes it rather than being a glob class
+fn index_brackets(rest: impl Iterator<Item = char>) -> bool {
+    let text: String = rest.take_while(|next| !next.is_whitespace()).collect();
+    let Some((index, after)) = text.strip_prefix('[').and_then(|text| text.split_once(']')) else {
+        return false;
+    };
+    let operand = |text: &str| {
+        let digits = text.strip_prefix('-').unwrap_or(text);
+        let number = !digits.is_empty() && digits.chars().all(|ch| ch.is_ascii_digit());
+        let name = text.starts_with(|ch: char| ch.is_alphabetic() || ch == '_')
+            && text.chars().all(|ch| ch.is_alphanumeric() || ch == '_');
+        number || name
+    };
+    let index = match index.split_once("..") {
+        Some((start, end)) => operand(start) && operand(end.strip_prefix('=').unwrap_or(end)),
+        None => operand(index),
+    };
+    index && !after.starts_with(is_word_char)
+}
+// the `{a,b}` or `{1..5}` group `rest` starts with, which must close before the word ends
+fn brace_group(rest: impl Iterator<Item = char>) -> Option<String> {
+    let mut group = String::new();
+    let mut depth = 0;
+    for next in rest {
+        if next.is_whitespace() || next == '"' {
+            return None;
+        }
+        group.push(next);
+        match next {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            break;
+        }
+    }
+    let inner = group.strip_prefix('{')?.strip_suffix('}')?;
+    // a sequence too long to expand is still a group, and an error once it's expanded
+    match brace_alternatives(inner) {
+        Ok(None) => None,
+        _ => Some(group),
+    }
+}
+// the most words one brace expansion may stand for, so a typo like `{1..1000000000}` is an
+// error rather than a shell eating all the memory
+const MAX_BRACE_WORDS: u128 = 100_000;
+
+fn too_many_words(group: &str) -> String {
+    format!("brace expansion {group} makes more than {MAX_BRACE_WORDS} words")
+}
+// the alternatives of a brace group's inside: its top-level comma-separated parts, or the
+// numbers or letters of a sequence like `1..10`, `01..10..3` or `a..e`
+fn brace_alternatives(inner: &str) -> Result<Option<Vec<String>>, String> {
+    let mut parts = vec![String::new()];
+    let mut depth = 0;
+    for ch in inner.chars() {
+        match ch {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        parts.last_mut().unwrap().push(ch);
+    }
+    if parts.len() > 1 {
+        return Ok(Some(parts));
+    }
+    let bounds: Vec<_> = inner.split("..").collect();
+    let step = match bounds[..] {
+        [_, _] => 1,
+        [_, _, step] => match step.parse::<i64>() {
+            Ok(step) => step.unsigned_abs().max(1),
+            Err(_) => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    // every item from `start` toward `end`, stopping at the last one that fits in an i64
+    let sequence = |start: i64, end: i64| {
+        let count = (start as i128).abs_diff(end as i128) / step as u128 + 1;
+        if count > MAX_BRACE_WORDS {
+            return Err(too_many_words(&format!("{{{inner}}}")));
+        }
+        let up = start <= end;
+        let mut next = Some(start);
+        Ok(std::iter::from_fn(move || {
+            let x = next.filter(|x| if up { *x <= end } else { *x >= end })?;
+            next = match up {
+                true => x.checked_add_unsigned(step),
+                false => x.checked_sub_unsigned(step),
+            };
+            Some(x)
+        }))
+    };
+    if let (Ok(start), Ok(end)) = (bounds[0].parse::<i64>(), bounds[1].parse::<i64>()) {
+        // `{01..10}` pads every number to the widest bound
+        let padded = [bounds[0], bounds[1]].iter().any(|bound| {
+            bound.trim_start_matches('-').len() > 1
+                && bound.trim_start_matches('-').starts_with('0')
+        });
+        let width = if padded {
+            bounds[0].len().max(bounds[1].len())
+        } else {
+            0
+        };
+        return Ok(Some(
+            sequence(start, end)?
+                .map(|x| format!("{x:0width$}"))
+                .collect(),
+        ));
+    }
+    let letter = |bound: &str| {
+        let mut chars = bound.chars();
+        chars
+            .next()
+            .filter(|ch| ch.is_ascii_alphabetic() && chars.next().is_none())
+    };
+    let (Some(start), Some(end)) = (letter(bounds[0]), letter(bounds[1])) else {
+        return Ok(None);
+    };
+    Ok(Some(
+        sequence(start as i64, end as i64)?
+            .map(|x| (x as u8 as char).to_string())
+            .collect(),
+    ))
+}
+// every word a brace expansion stands for, left to right, as in `a{b,c}d` to `abd acd`;
+// `${name}` is interpolation and stays as it is
+fn expand_braces(word: &str) -> Result<Vec<String>, String> {
+    let mut search = 0;
+    while let Some(offset) = word[search..].find('{') {
+        let open = search + offset;
+        search = open + 1;
+        if word[..open].ends_with('$') {
+            continue;
+        }
+        let Some(group) = brace_group(word[open..].chars()) else {
+            continue;
+        };
+        let (prefix, suffix) = (&word[..open], &word[open + group.len()..]);
+        let alternatives = brace_alternatives(&group[1..group.len() - 1])?.unwrap_or_default();
+        let suffixes = expand_braces(suffix)?;
+        let mut middles = Vec::new();
+        for alternative in &alternatives {
+            middles.extend(expand_braces(alternative)?);
+        }
+        if middles.len() as u128 * suffixes.len() as u128 > MAX_BRACE_WORDS {
+            return Err(too_many_words(word));
+        }
+        return Ok(middles
+            .iter()
+            .flat_map(|middle| {
+                suffixes
+                    .iter()
+                    .map(move |suffix| format!("{prefix}{middle}{suffix}"))
+            })
+            .collect());
+    }
+    Ok(vec![word.to_string()])
+}
+// a redirection of particular descriptors, split into the descriptor (`2`, `&` for stdout and
+// stderr both, or nothing), the arrow and what follows it: nothing when a path comes next,
+// `&1` to copy another descriptor, `&-` to close it or `null` for /dev/null
+pub fn split_fd_redirect(word: &str) -> Option<(&str, &str, &str)> {
+    let arrow = word.find(['-', '<', '>'])?;
+    let (fd, rest) = word.split_at(arrow);
+    let op = ["->", ">>", "<-"]
+        .into_iter()
+        .find(|op| rest.starts_with(op))?;
+    let target = &rest[op.len()..];
+    let valid_fd = match fd {
+        "&" => op != "<-" && !target.starts_with('&'),
+        "" => true,
+        _ => fd.chars().all(|ch| ch.is_ascii_digit()) && fd.parse::<u16>().is_ok(),
+    };
+    let valid_target = match target.strip_prefix('&') {
+        Some(source) => source == "-" || source.parse::<u16>().is_ok(),
+        None => target.is_empty() || target == "null",
+    };
+    // plain `->`, `>>` and `<-` are tokens of their own
+    let plain = fd.is_empty() && target.is_empty();
+    (valid_fd && valid_target && !plain).then_some((fd, op, target))
+}
+// the start of a here-doc: `<<EOF`, `<<-EOF` to strip the body's indentation, or `<<'EOF'`
+// to keep `$` as it is
+pub struct HereDocStart {
+    pub terminator: String,
+    pub indented: bool,
+    pub raw: bool,
+}
+pub fn here_doc_start(word: &str) -> Option<HereDocStart> {
+    let rest = word.strip_prefix("<<")?;
+    let (rest, indented) = match rest.strip_prefix('-') {
+        Some(rest) => (rest, true),
+        None => (rest, false),
+    };
+    let quoted = rest
+        .strip_prefix('\'')
+        .and_then(|rest| rest.strip_suffix('\''));
+    let terminator = quoted.unwrap_or(rest);
+    let valid = !terminator.is_empty()
+        && terminator
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || ch == '_');
+    valid.then(|| HereDocStart {
+        terminator: terminator.to_string(),
+        indented,
+        raw: quoted.is_some(),
+    })
+}
+// whether a here-doc's body line is its terminator
+pub fn ends_here_doc(line: &str, start: &HereDocStart) -> bool {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    match start.indented {
+        true => line.trim_start() == start.terminator,
+        false => line == start.terminator,
+    }
+}
+// the lines of a here-doc's body up to its terminator, or to the end of the source if it
+// never comes; the common indentation goes when the here-doc is indented
+fn here_doc_body(src: &mut Peekable<Chars>, start: &HereDocStart, line: &mut usize) -> String {
+    let mut body = Vec::new();
+    while src.peek().is_some() {
+        let mut text = String::new();
+        for next in src.by_ref() {
+            text.push(next);
+            if next == '\n' {
+                break;
+            }
+        }
+        *line += 1;
+        if ends_here_doc(&text, start) {
+            break;
+        }
+        body.push(text);
+    }
+    if start.indented {
+        let indent = body
+            .iter()
+            .filter(|text| !text.trim().is_empty())
+            .map(|text| text.len() - text.trim_start().len())
+            .min()
+            .unwrap_or(0);
+        for text in &mut body {
+            let strip = indent.min(text.len() - text.trim_start().len());
+            text.drain(..strip);
+        }
+    }
+    body.concat()
+}
+// true when `tokens` ends in a command line like `ls -a`: a word at the start of a statement
+// followed only by literal arguments, the same shape the parser turns into a command call
+fn in_command_args(tokens: &[Token]) -> bool {
+    command_args(tokens).is_some()
+}
+// where the process substitution `tokens` ends in starts, if it ends in one
+fn substitution_start(tokens: &[Token]) -> Option<usize> {
+    if tokens.last() != Some(&Token::CloseParen) {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().rev() {
+        match token {
+            Token::CloseParen => depth += 1,
+            Token::OpenParen => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return match tokens[..i].last() {
+                Some(Token::ProcessIn | Token::ProcessOut) => Some(i - 1),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+// how many arguments follow the command word `tokens` ends in, if it ends in one
+fn command_args(tokens: &[Token]) -> Option<usize> {
+    let is_literal = |token: &Token| {
+        matches!(
+            token,
+            Token::Int(_)
+                | Token::Float(_)
+                | Token::Bool(_)
+                | Token::String(_)
+                | Token::Identifier(_)
+                | Token::ShellWord(_)
+        )
+    };
+    // a process substitution like `<(sort a)` is one more argument
+    let mut start = tokens.len();
+    while start > 0 {
+        if is_literal(&tokens[start - 1]) {
+            start -= 1;
+        } else if let Some(open) = substitution_start(&tokens[..start]) {
+            start = open;
+        } else {
+            break;
+        }
+    }
+    let statement_start = start == 0
+        || matches!(
+            tokens[start - 1],
+            Token::Newline
+                | Token::AndThen
+                | Token::OrElse
+                | Token::Pipe
+                | Token::Background
+                | Token::If
+                | Token::While
+                | Token::Else
+                | Token::OpenParen
+                | Token::OpenBrace
+                | Token::Comma
+                | Token::Arrow
+        );
+    let command = matches!(
+        tokens.get(start),
+        Some(Token::Identifier(_) | Token::ShellWord(_))
+    );
+    (statement_start && command).then(|| tokens.len() - start - 1)
+}
 pub struct Lexer {
     number_regex: Regex,
+    range_regex: Regex,
     identifier_regex: Regex,
 }
 impl Lexer {
     pub fn new() -> Self {
         Self {
-            number_regex: Regex::new(r"^-?\d+(\.\d+)?$").unwrap(),
+            // a 0x/0o/0b integer or a decimal with optional fraction and exponent, unsigned
+            // since a leading `-` is lexed separately; `_` may separate digits but never lead or trail them
+            number_regex: Regex::new(concat!(
+                r"^(?:0x([[:xdigit:]]+(?:_[[:xdigit:]]+)*)|0o([0-7]+(?:_[0-7]+)*)",
+                r"|0b([01]+(?:_[01]+)*)|(\d+(?:_\d+)*(?:\.\d+(?:_\d+)*)?(?:[eE][+-]?\d+)?))$",
+            ))
+            .unwrap(),
+            range_regex: Regex::new(r"^(-?\w*)(\.\.=?)(-?\w*)$").unwrap(),
             identifier_regex: Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap(),
         }
     }
 
-    pub fn tokenize(&self, source_code: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    // the tokens of `source_code`, along with the line each one starts on
+    pub fn tokenize(&self, source_code: &str) -> Result<(Vec<Token>, Vec<usize>), LexError> {
         let mut tokens = Vec::new();
+        let mut lines = Vec::new();
+        let mut line = 1;
         let mut src = source_code.chars().peekable();
         macro_rules! push_next {
             ($x:expr) => {{
@@ -79,25 +528,59 @@ impl Lexer {
                 src.next();
             }};
         }
-        while let Some(current) = src.peek() {
+        let mut spaced = false;
+        // the index of a number argument that's kept as written unless an operator follows it
+        let mut number_arg = None;
+        // here-docs opened on the current line, as (token index, start), whose bodies follow it
+        let mut here_docs: Vec<(usize, HereDocStart)> = Vec::new();
+        while let Some(&current) = src.peek() {
+            let start_line = line;
+            let error = |message| LexError {
+                message,
+                line: start_line,
+            };
+            let after_space = std::mem::take(&mut spaced);
             match current {
                 '(' => push_next!(Token::OpenParen),
                 ')' => push_next!(Token::CloseParen),
-                '[' => push_next!(Token::OpenBracket),
+                // `ls [ab].log` starts a glob, but `xs[0]` and `f(xs [0])` index
+                '[' if !(after_space && in_command_args(&tokens) && glob_class(src.clone())) => {
+                    push_next!(Token::OpenBracket)
+                }
                 ']' => push_next!(Token::CloseBracket),
-                '{' => push_next!(Token::OpenBrace),
+                // `echo {a,b}` is a brace expansion, but `if x {a}` still opens a block
+                '{' if !(after_space
+                    && in_command_args(&tokens)
+                    && brace_group(src.clone()).is_some()) =>
+                {
+                    push_next!(Token::OpenBrace)
+                }
                 '}' => push_next!(Token::CloseBrace),
                 ',' => push_next!(Token::Comma),
-                '!' => push_next!(Token::Not),
+                ':' => push_next!(Token::Colon),
+                '!' if !matches!(src.clone().nth(1), Some('=')) => push_next!(Token::Not),
+                '#' => while src.next_if(|next| *next != '\n').is_some() {},
                 ' ' | '\t' => {
                     src.next();
+                    spaced = true;
+                }
+                '\n' => {
+                    push_next!(Token::Newline);
+                    line += 1;
+                    for (index, start) in here_docs.drain(..) {
+                        let body = here_doc_body(&mut src, &start, &mut line);
+                        tokens[index] = match start.raw {
+                            true => Token::RawHereDoc(body),
+                            false => Token::HereDoc(body),
+                        };
+                    }
                 }
-                '\n' => push_next!(Token::Newline),
                 '"' => {
                     let mut string = String::new();
                     src.next();
                     while let Some(next) = src.peek() {
                         if *next != '"' {
+                            line += usize::from(*next == '\n');
                             string.push(*next);
                             src.next();
                         } else {
@@ -107,49 +590,181 @@ impl Lexer {
                     }
                     tokens.push(Token::String(string));
                 }
-                _ if current.is_alphanumeric() || "_'+-*/%=<>&|!.".contains(*current) => {
+                _ if is_word_char(current) || current == '[' || current == '{' => {
                     let mut word = String::new();
-                    while let Some(next) = src.peek() {
-                        if "_'+-*/%=<>&|!.".contains(*next) || next.is_alphanumeric() {
-                            word.push(*next);
+                    // a command's arguments may hold glob classes and brace expansions,
+                    // as in `ls log[0-9].txt` or `cp file.{txt,bak}`, though `echo xs[0]`
+                    // still indexes
+                    let argument = in_command_args(&tokens);
+                    while let Some(&next) = src.peek() {
+                        // `:` joins words like `host:8080` but ends `key:` in `{key: value}`
+                        let inner_colon =
+                            next == ':' && src.clone().nth(1).is_some_and(is_word_char);
+                        if is_word_char(next) || inner_colon {
+                            word.push(next);
                             src.next();
+                        } else if argument && let Some(group) = brace_group(src.clone()) {
+                            src.nth(group.chars().count() - 1);
+                            word.push_str(&group);
+                        } else if argument
+                            && !word.starts_with('$')
+                            && !(self.identifier_regex.is_match(&word)
+                                && index_brackets(src.clone()))
+                            && glob_class(src.clone())
+                        {
+                            for next in src.by_ref() {
+                                word.push(next);
+                                if next == ']' && !word.ends_with("[]") {
+                                    break;
+                                }
+                            }
                         } else {
                             break;
                         }
                     }
+                    // NAME="quoted value" stays one word, for assignments and env overrides
+                    if src.peek() == Some(&'"')
+                        && word
+                            .strip_suffix('=')
+                            .is_some_and(|name| self.identifier_regex.is_match(name))
+                    {
+                        src.next();
+                        while let Some(next) = src.next_if(|next| *next != '"') {
+                            line += usize::from(next == '\n');
+                            word.push(next);
+                        }
+                        src.next();
+                    }
                     match word.as_str() {
-                        "+" => tokens.push(Token::Add),
+                        // a `-` with nothing to subtract is an argument, as in `cd -`
+                        "-" if ends_operand(src.clone()) => tokens.push(Token::ShellWord(word)),
                         "-" => tokens.push(Token::Sub),
+                        // a lone `*` or `**` ending a command line is a glob, as in `ls *`, and so is
+                        // one among its arguments unless it sits after a number, as in `echo 2 * 3`;
+                        // right after the command word it multiplies, as in `x * 2`. A lone `/`,
+                        // `+` or `%` is a plain argument the same way, as in `cd /`
+                        "*" | "**" | "/" | "+" | "%"
+                            if command_args(&tokens).is_some_and(|args| {
+                                ends_operand(src.clone()) || args > 0 && number_arg.is_none()
+                            }) =>
+                        {
+                            tokens.push(Token::ShellWord(word))
+                        }
+                        "+" => tokens.push(Token::Add),
                         "*" => tokens.push(Token::Mul),
                         "/" => tokens.push(Token::Div),
                         "%" => tokens.push(Token::Mod),
+                        "**" => tokens.push(Token::Pow),
+                        "//" => tokens.push(Token::FloorDiv),
+                        "+=" => tokens.push(Token::AddAssign),
+                        "-=" => tokens.push(Token::SubAssign),
+                        "*=" => tokens.push(Token::MulAssign),
+                        "/=" => tokens.push(Token::DivAssign),
                         "=" => tokens.push(Token::Equals),
                         "|" => tokens.push(Token::Pipe),
                         "||" => tokens.push(Token::OrElse),
                         "&&" => tokens.push(Token::AndThen),
+                        "&" => tokens.push(Token::Background),
+                        // `diff <(sort a) <(sort b)` passes commands' output as files, and
+                        // `-> >(gzip -> out.gz)` sends output to one
+                        "<" | ">"
+                            if src.peek() == Some(&'(')
+                                && (argument
+                                    || matches!(
+                                        tokens.last(),
+                                        Some(
+                                            Token::RedirectIn
+                                                | Token::RedirectOut
+                                                | Token::RedirectOutAppend
+                                                | Token::FdRedirect(_)
+                                        )
+                                    )) =>
+                        {
+                            tokens.push(match word.as_str() {
+                                "<" => Token::ProcessIn,
+                                _ => Token::ProcessOut,
+                            })
+                        }
                         ">" => tokens.push(Token::GreaterThan),
                         "<" => tokens.push(Token::LessThan),
                         ">=" => tokens.push(Token::GreaterThanEqual),
                         "<=" => tokens.push(Token::LessThanEqual),
                         "==" => tokens.push(Token::EqualTo),
                         "!=" => tokens.push(Token::NotEqualTo),
+                        "=~" => tokens.push(Token::Matches),
+                        "=>" => tokens.push(Token::Arrow),
                         "<-" => tokens.push(Token::RedirectIn),
+                        _ if split_fd_redirect(&word).is_some() => {
+                            tokens.push(Token::FdRedirect(word))
+                        }
+                        "<<<" => tokens.push(Token::HereString),
+                        // `<<<$x` glued to what it feeds
+                        _ if word.starts_with("<<<") => {
+                            tokens.push(Token::HereString);
+                            tokens.push(Token::ShellWord(word[3..].to_string()));
+                        }
+                        // the body is filled in once the line ends
+                        _ if let Some(start) = here_doc_start(&word) => {
+                            tokens.push(Token::HereDoc(String::new()));
+                            here_docs.push((tokens.len() - 1, start));
+                        }
                         "->" => tokens.push(Token::RedirectOut),
                         ">>" => tokens.push(Token::RedirectOutAppend),
                         "not" => tokens.push(Token::Not),
                         "and" => tokens.push(Token::And),
                         "or" => tokens.push(Token::Or),
+                        // bitwise ops are words since `&`, `|` and `>>` already belong to the shell
+                        "band" => tokens.push(Token::BitAnd),
+                        "bor" => tokens.push(Token::BitOr),
+                        "bxor" => tokens.push(Token::BitXor),
+                        "shl" => tokens.push(Token::ShiftLeft),
+                        "shr" => tokens.push(Token::ShiftRight),
                         "if" => tokens.push(Token::If),
                         "while" => tokens.push(Token::While),
                         "function" => tokens.push(Token::Function),
                         "for" => tokens.push(Token::For),
+                        "else" => tokens.push(Token::Else),
+                        "return" => tokens.push(Token::Return),
+                        "let" | "local" => tokens.push(Token::Let),
+                        "match" => tokens.push(Token::Match),
+                        "in" => tokens.push(Token::In),
+                        "try" => tokens.push(Token::Try),
+                        "catch" => tokens.push(Token::Catch),
+                        "finally" => tokens.push(Token::Finally),
+                        "throw" => tokens.push(Token::Throw),
                         "true" => tokens.push(Token::Bool(Bool::True)),
                         "false" => tokens.push(Token::Bool(Bool::False)),
                         _ if word.starts_with('\'') => {
                             tokens.push(Token::ShellWord(word.split_off(1)))
                         }
+                        _ if argument && word.contains('{') => tokens.extend(
+                            expand_braces(&word)
+                                .map_err(error)?
+                                .into_iter()
+                                .map(Token::ShellWord),
+                        ),
+                        // a `-` glued to a command's argument is a flag, as in `ls -la` or `head -1`;
+                        // anywhere else it negates what follows, as in `x = -1` or `xs[-1]`
+                        _ if word.starts_with('-') => {
+                            if in_command_args(&tokens) {
+                                tokens.push(Token::ShellWord(word))
+                            } else {
+                                self.push_part(&mut tokens, &word).map_err(error)?;
+                            }
+                        }
+                        // `1..10` splits into a range, but `cd ..` and `ls ../src` keep their paths
+                        _ if self.range_regex.is_match(&word) && !in_command_args(&tokens) => {
+                            self.push_part(&mut tokens, &word).map_err(error)?
+                        }
+                        // a number among a command's arguments is passed on as written, as in
+                        // `chmod 0o644 f`, unless an operator after it makes it part of an
+                        // expression, as in `echo 2 * 3`
+                        _ if self.number_regex.is_match(&word) && in_command_args(&tokens) => {
+                            number_arg = Some(tokens.len());
+                            tokens.push(Token::ShellWord(word))
+                        }
                         _ if self.number_regex.is_match(&word) => {
-                            tokens.push(Token::Number(word.parse()?))
+                            self.push_number(&mut tokens, &word).map_err(error)?
                         }
                         _ if self.identifier_regex.is_match(&word) => {
                             tokens.push(Token::Identifier(word))
@@ -157,10 +772,251 @@ impl Lexer {
                         _ => tokens.push(Token::ShellWord(word)),
                     }
                 }
-                x => panic!("unknown token: {:?}", x),
+                x => return Err(error(format!("unexpected character {x:?}"))),
             }
+            if let Some(index) = number_arg
+                && index + 1 < tokens.len()
+            {
+                number_arg = None;
+                if let (Token::ShellWord(word), true) =
+                    (&tokens[index], is_binary_operator(&tokens[index + 1]))
+                {
+                    tokens[index] = self.number(word, false).map_err(error)?;
+                }
+            }
+            lines.resize(tokens.len(), start_line);
         }
         tokens.push(Token::Eof);
-        Ok(tokens)
+        lines.push(line);
+        Ok((tokens, lines))
+    }
+    // the tokens of a piece of a word, like the ends of a range, lexed onto `tokens` so that
+    // a `-` there can negate a number that follows
+    fn push_part(&self, tokens: &mut Vec<Token>, part: &str) -> Result<(), String> {
+        if let Some(rest) = part.strip_prefix('-') {
+            tokens.push(Token::Sub);
+            self.push_part(tokens, rest)
+        } else if self.number_regex.is_match(part) {
+            self.push_number(tokens, part)
+        } else if let Some(captures) = self.range_regex.captures(part) {
+            self.push_part(tokens, &captures[1])?;
+            tokens.push(match &captures[2] {
+                "..=" => Token::DotDotEqual,
+                _ => Token::DotDot,
+            });
+            self.push_part(tokens, &captures[3])
+        } else {
+            let mut rest = self.tokenize(part).map_err(|err| err.message)?.0;
+            rest.pop();
+            tokens.extend(rest);
+            Ok(())
+        }
+    }
+    // `-9223372036854775808` only fits once negated, so a negating `-` before a literal
+    // that overflows is folded into it
+    fn push_number(&self, tokens: &mut Vec<Token>, word: &str) -> Result<(), String> {
+        match self.number(word, false) {
+            Err(_) if negates(tokens) => {
+                let token = self.number(word, true)?;
+                *tokens.last_mut().unwrap() = token;
+            }
+            token => tokens.push(token?),
+        }
+        Ok(())
+    }
+    // a word already known to match `number_regex`, negated if asked
+    fn number(&self, word: &str, negative: bool) -> Result<Token, String> {
+        let captures = self.number_regex.captures(word).unwrap();
+        let (digits, radix) = match (captures.get(1), captures.get(2), captures.get(3)) {
+            (Some(hex), _, _) => (hex.as_str(), 16),
+            (_, Some(octal), _) => (octal.as_str(), 8),
+            (_, _, Some(binary)) => (binary.as_str(), 2),
+            _ => (&captures[4], 10),
+        };
+        let digits = digits.replace('_', "");
+        if radix == 10 && digits.contains(['.', 'e', 'E']) {
+            return digits
+                .parse()
+                .map(Token::Float)
+                .map_err(|err| format!("{word}: {err}"));
+        }
+        let digits = match negative {
+            true => format!("-{digits}"),
+            false => digits,
+        };
+        match i64::from_str_radix(&digits, radix) {
+            Ok(x) => Ok(Token::Int(x)),
+            Err(_) if negative => Err(format!("integer literal out of range: -{word}")),
+            Err(_) => Err(format!("integer literal out of range: {word}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Token::*;
+
+    fn lex(source: &str) -> Vec<Token> {
+        let mut tokens = Lexer::new().tokenize(source).unwrap().0;
+        assert_eq!(tokens.pop(), Some(Eof));
+        tokens
+    }
+
+    fn word(text: &str) -> Token {
+        ShellWord(text.into())
+    }
+
+    fn ident(name: &str) -> Token {
+        Identifier(name.into())
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            lex("x = 0x1F + 1_000"),
+            [ident("x"), Equals, Int(31), Add, Int(1000)]
+        );
+        assert_eq!(lex("x = 1.5e3"), [ident("x"), Equals, Float(1500.0)]);
+        assert_eq!(
+            lex("x = -9223372036854775808"),
+            [ident("x"), Equals, Int(i64::MIN)]
+        );
+        assert_eq!(lex("x = -1"), [ident("x"), Equals, Sub, Int(1)]);
+        assert_eq!(
+            lex("x = 1..=3"),
+            [ident("x"), Equals, Int(1), DotDotEqual, Int(3)]
+        );
+    }
+
+    #[test]
+    fn number_arguments_keep_their_text() {
+        assert_eq!(
+            lex("chmod 0o644 f.txt"),
+            [ident("chmod"), word("0o644"), word("f.txt")]
+        );
+        assert_eq!(
+            lex("echo 1.10 007 1_000 1e3 0x1F"),
+            [
+                ident("echo"),
+                word("1.10"),
+                word("007"),
+                word("1_000"),
+                word("1e3"),
+                word("0x1F")
+            ]
+        );
+        assert_eq!(
+            lex("echo 18446744073709551615"),
+            [ident("echo"), word("18446744073709551615")]
+        );
+        assert_eq!(lex("echo 2 * 3"), [ident("echo"), Int(2), Mul, Int(3)]);
+        assert_eq!(
+            lex("echo 0x10 == 16"),
+            [ident("echo"), Int(16), EqualTo, Int(16)]
+        );
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(lex("ls -la"), [ident("ls"), word("-la")]);
+        assert_eq!(lex("cd -"), [ident("cd"), word("-")]);
+        assert_eq!(
+            lex("x = a - b"),
+            [ident("x"), Equals, ident("a"), Sub, ident("b")]
+        );
+    }
+
+    #[test]
+    fn globs() {
+        assert_eq!(lex("ls *"), [ident("ls"), word("*")]);
+        assert_eq!(lex("x * 2"), [ident("x"), Mul, Int(2)]);
+        assert_eq!(lex("ls [ab]*"), [ident("ls"), word("[ab]*")]);
+        assert_eq!(lex("ls log[0-9].txt"), [ident("ls"), word("log[0-9].txt")]);
+        assert_eq!(lex("cd /"), [ident("cd"), word("/")]);
+        assert_eq!(lex("ls ../src"), [ident("ls"), word("../src")]);
+    }
+
+    #[test]
+    fn indexing() {
+        assert_eq!(
+            lex("echo xs[0]"),
+            [
+                ident("echo"),
+                ident("xs"),
+                OpenBracket,
+                Int(0),
+                CloseBracket
+            ]
+        );
+        assert_eq!(
+            lex("echo xs[i]"),
+            [
+                ident("echo"),
+                ident("xs"),
+                OpenBracket,
+                ident("i"),
+                CloseBracket
+            ]
+        );
+        assert_eq!(
+            lex("echo xs[0..2]"),
+            [
+                ident("echo"),
+                ident("xs"),
+                OpenBracket,
+                Int(0),
+                DotDot,
+                Int(2),
+                CloseBracket
+            ]
+        );
+        assert_eq!(lex("echo log[a-c]"), [ident("echo"), word("log[a-c]")]);
+    }
+
+    #[test]
+    fn braces() {
+        assert_eq!(
+            lex("echo {a,b}.txt"),
+            [ident("echo"), word("a.txt"), word("b.txt")]
+        );
+    }
+
+    #[test]
+    fn brace_sequences_stop_at_their_ends() {
+        assert_eq!(
+            lex("echo {9223372036854775806..9223372036854775807}"),
+            [
+                ident("echo"),
+                word("9223372036854775806"),
+                word("9223372036854775807")
+            ]
+        );
+        assert_eq!(
+            lex("echo {1..10..-9223372036854775808}"),
+            [ident("echo"), word("1")]
+        );
+        assert_eq!(
+            lex("echo {05..1..2}"),
+            [ident("echo"), word("05"), word("03"), word("01")]
+        );
+        let err = Lexer::new().tokenize("echo {1..1000000000}").unwrap_err();
+        assert!(err.message.contains("more than"), "{}", err.message);
+        assert!(Lexer::new().tokenize("echo {1..1000}{1..1000}").is_err());
+    }
+
+    #[test]
+    fn redirects() {
+        assert_eq!(lex("cmd 2->&1"), [ident("cmd"), FdRedirect("2->&1".into())]);
+        assert_eq!(
+            lex("cat <<EOF\nhi\nEOF\n"),
+            [ident("cat"), HereDoc("hi\n".into()), Newline]
+        );
+    }
+
+    #[test]
+    fn errors_have_lines() {
+        let err = Lexer::new().tokenize("echo hi\necho ~`\n").unwrap_err();
+        assert_eq!(err.line, 2);
     }
 }
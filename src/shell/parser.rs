@@ -1,6 +1,47 @@
-use super::ast::{Ast, BinaryOp, Expr, UnaryOp};
-use super::lexer::{Bool, Token};
+use super::ast::{BinaryOp, Expr, MatchArm, Pattern, RedirectKind, Redirection, UnaryOp};
+use super::lexer::{Bool, Token, split_fd_redirect};
 use super::values::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+// input that doesn't parse, with the line it goes wrong on
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+type PResult<T> = Result<T, ParseError>;
+
+// roughly how a token was written, for error messages
+fn shown(token: &Token) -> String {
+    match token {
+        Token::Int(x) => x.to_string(),
+        Token::Float(x) => x.to_string(),
+        Token::String(text) => format!("{text:?}"),
+        Token::Identifier(word) | Token::ShellWord(word) | Token::FdRedirect(word) => {
+            format!("`{word}`")
+        }
+        Token::Newline => "end of line".to_string(),
+        Token::Eof => "end of input".to_string(),
+        Token::OpenParen => "'('".to_string(),
+        Token::CloseParen => "')'".to_string(),
+        Token::OpenBrace => "'{'".to_string(),
+        Token::CloseBrace => "'}'".to_string(),
+        Token::OpenBracket => "'['".to_string(),
+        Token::CloseBracket => "']'".to_string(),
+        Token::Comma => "','".to_string(),
+        Token::Colon => "':'".to_string(),
+        Token::Equals => "'='".to_string(),
+        Token::Arrow => "'=>'".to_string(),
+        other => format!("{other:?}"),
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Precedence {
@@ -13,11 +54,17 @@ pub enum Precedence {
     CommandArg,  // used for collecting args of a command call
     Or,          // or
     And,         // and
-    Equality,    // ==, !=
-    Comparison,  // <, <=, >, >=
+    Equality,    // ==, !=, =~
+    Comparison,  // <, <=, >, >=, in
+    Range,       // .., ..=
+    BitOr,       // bor
+    BitXor,      // bxor
+    BitAnd,      // band
+    Shift,       // shl, shr
     Term,        // +, -
-    Factor,      // *, /, %
-    Unary,       // !, not
+    Factor,      // *, /, //, %
+    Unary,       // !, not, -
+    Exponent,    // ** (right-associative, binds tighter than a leading -)
     CallOrIndex, // function calls, array indexing: f(x), x[0]
     Primary,     // literals, identifiers, (grouped), blocks
 }
@@ -35,10 +82,16 @@ impl Precedence {
             Or => And,
             And => Equality,
             Equality => Comparison,
-            Comparison => Term,
+            Comparison => Range,
+            Range => BitOr,
+            BitOr => BitXor,
+            BitXor => BitAnd,
+            BitAnd => Shift,
+            Shift => Term,
             Term => Factor,
             Factor => Unary,
-            Unary => CallOrIndex,
+            Unary => Exponent,
+            Exponent => CallOrIndex,
             CallOrIndex => Primary,
             Primary => Primary,
         }
@@ -46,7 +99,8 @@ impl Precedence {
 }
 macro_rules! match_literals {
     ($x:pat) => {
-        Token::Number($x)
+        Token::Int($x)
+            | Token::Float($x)
             | Token::Bool($x)
             | Token::String($x)
             | Token::Identifier($x)
@@ -55,7 +109,19 @@ macro_rules! match_literals {
 }
 macro_rules! match_keywords {
     () => {
-        Token::If | Token::While | Token::Function | Token::For
+        Token::If
+            | Token::While
+            | Token::Function
+            | Token::For
+            | Token::Else
+            | Token::Return
+            | Token::Let
+            | Token::Match
+            | Token::In
+            | Token::Try
+            | Token::Catch
+            | Token::Finally
+            | Token::Throw
     };
 }
 macro_rules! match_shell_ops {
@@ -63,22 +129,12 @@ macro_rules! match_shell_ops {
         Token::AndThen
             | Token::OrElse
             | Token::Pipe
+            | Token::Background
             | Token::RedirectIn
             | Token::RedirectOut
             | Token::RedirectOutAppend
-    };
-}
-macro_rules! match_ops {
-    () => {
-        Token::Equals
-            | Token::Add
-            | Token::Sub
-            | Token::Mul
-            | Token::Div
-            | Token::Mod
-            | Token::And
-            | Token::Or
-            | Token::Not
+            | Token::FdRedirect(_)
+            | Token::HereString
     };
 }
 macro_rules! match_open_groupers {
@@ -91,35 +147,125 @@ macro_rules! match_close_groupers {
         Token::CloseParen | Token::CloseBrace | Token::CloseBracket
     };
 }
+// adds redirections to a command, after any it already has
+fn redirect(expr: Expr, mut redirects: Vec<Redirection>) -> Expr {
+    match expr {
+        Expr::Redirect {
+            expr,
+            redirects: mut earlier,
+        } => {
+            earlier.append(&mut redirects);
+            Expr::Redirect {
+                expr,
+                redirects: earlier,
+            }
+        }
+        expr => Expr::Redirect {
+            expr: Box::new(expr),
+            redirects,
+        },
+    }
+}
+// whether a token can start a command's argument
+fn starts_arg(token: &Token) -> bool {
+    matches!(
+        token,
+        match_literals!(_) | Token::ProcessIn | Token::ProcessOut
+    )
+}
+// whether an expression is a command line: a command, or a chain or redirect starting with one
+fn runs_command(expr: &Expr) -> bool {
+    match expr {
+        Expr::CommandCall { .. } | Expr::Background(_) => true,
+        Expr::AndThen { left, .. } | Expr::OrElse { left, .. } | Expr::Pipe { left, .. } => {
+            runs_command(left)
+        }
+        Expr::Redirect { expr, .. } => runs_command(expr),
+        _ => false,
+    }
+}
+// whether `( expr )` is a subshell rather than a grouping: it runs a command, defines or
+// assigns something, or is a bare word that runs as a command at the start of a statement,
+// as in `( cd )`; `if (done)` and `y = (x)` still just read `x`
+fn isolates(expr: &Expr, statement: bool) -> bool {
+    match expr {
+        Expr::Assign { .. }
+        | Expr::IndexAssign { .. }
+        | Expr::Destructure { .. }
+        | Expr::Let { .. }
+        | Expr::FuncDef { .. } => true,
+        Expr::Identifier(_) | Expr::ShellWord(_) => statement,
+        expr => runs_command(expr),
+    }
+}
+static EOF: Token = Token::Eof;
+
 pub struct Parser {
     tokens: Vec<Token>,
+    lines: Vec<usize>, // the line each token starts on
     current: usize,
+    aliases: HashMap<String, Vec<Token>>, // the tokens each alias stands for
 }
 
 impl Parser {
     pub fn new() -> Self {
         Parser {
             tokens: Vec::new(),
+            lines: Vec::new(),
             current: 0,
+            aliases: HashMap::new(),
         }
     }
+    // past the end there's only more of the end
     fn at(&self) -> &Token {
-        &self.tokens[self.current]
+        self.tokens.get(self.current).unwrap_or(&EOF)
     }
     fn eat(&mut self) -> &Token {
         self.current += 1;
-        &self.tokens[self.current - 1]
+        self.tokens.get(self.current - 1).unwrap_or(&EOF)
     }
-    fn expect(&mut self, token: Token, err: &str) -> &Token {
-        self.current += 1;
-        let prev = &self.tokens[self.current - 1];
-        if *prev != token {
-            panic!(
-                "Parser Error:\n{}\nExpected: {:?}\nFound: {:?}",
-                err, token, prev
-            )
+    fn expect(&mut self, token: Token, err: &str) -> PResult<()> {
+        if *self.at() != token {
+            return Err(self.error(format!("{err}, found {}", shown(self.at()))));
+        }
+        self.eat();
+        Ok(())
+    }
+    // the line the current token is on
+    fn line(&self) -> usize {
+        let last = self.lines.len().saturating_sub(1);
+        self.lines.get(self.current.min(last)).copied().unwrap_or(0)
+    }
+    // an error at the current token
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            message,
+            line: self.line(),
+        }
+    }
+    fn unexpected(&self) -> ParseError {
+        self.error(format!("unexpected {}", shown(self.at())))
+    }
+    // `target = value`, where the target is a variable or an index into one, as in `xs[0]`
+    // or `config["hosts"][1]`
+    fn assignment(&self, target: Expr, value: Expr) -> PResult<Expr> {
+        let mut base = &target;
+        while let Expr::Index { target, .. } = base {
+            base = target;
+        }
+        let indexes_variable = matches!(base, Expr::Identifier(_));
+        match (target, indexes_variable) {
+            (Expr::Identifier(name), _) => Ok(Expr::Assign {
+                name,
+                value: Box::new(value),
+            }),
+            (Expr::Index { target, index }, true) => Ok(Expr::IndexAssign {
+                target,
+                index,
+                value: Box::new(value),
+            }),
+            _ => Err(self.error("invalid assignment target".into())),
         }
-        prev
     }
     fn peek_next(&self) -> Option<&Token> {
         if self.current + 2 < self.tokens.len() {
@@ -136,42 +282,324 @@ impl Parser {
         }
     }
     fn is_arg(&self) -> bool {
-        matches!(self.at(), match_literals!(_))
+        starts_arg(self.at())
     }
-    pub fn produce_ast(&mut self, tokens: Vec<Token>) -> Ast {
+    fn skip_newlines(&mut self) {
+        while *self.at() == Token::Newline {
+            self.eat();
+        }
+    }
+    // starts on `tokens`, whose statements then come one at a time from `next_statement`
+    pub fn load(&mut self, tokens: Vec<Token>, lines: Vec<usize>) {
         self.tokens = tokens;
-        println!("{:#?}", self.tokens);
-        let ast = Ast {
-            expr: self.parse_expression(Precedence::Lowest),
-        };
-        self.tokens.clear();
+        self.lines = lines;
         self.current = 0;
-        ast
     }
-    fn collect_args(&mut self, prec: Precedence) -> Vec<Expr> {
+    // the next statement, or `None` at the end; it's parsed with `aliases` as they are now,
+    // so an alias applies from the statement after the one defining it
+    pub fn next_statement(
+        &mut self,
+        aliases: HashMap<String, Vec<Token>>,
+    ) -> PResult<Option<Expr>> {
+        self.aliases = aliases;
+        self.skip_newlines();
+        match self.at() {
+            Token::Eof => Ok(None),
+            _ => self.parse_statement().map(Some),
+        }
+    }
+    // statements remember their line so errors can say where they happened
+    fn parse_statement(&mut self) -> PResult<Expr> {
+        let line = self.line();
+        let mut expr = self.parse_expression(Precedence::Lowest)?;
+        // a trailing `&` runs the whole statement as a background job
+        if *self.at() == Token::Background {
+            self.eat();
+            expr = Expr::Background(Box::new(expr));
+        }
+        Ok(Expr::Statement {
+            line,
+            expr: Box::new(expr),
+        })
+    }
+    fn parse_block(&mut self) -> PResult<Expr> {
+        let mut expression = Vec::new();
+        self.expect(Token::OpenBrace, "expected '{'")?;
+        self.skip_newlines();
+        while *self.at() != Token::CloseBrace {
+            let expr = self.parse_statement()?;
+            expression.push(expr);
+            self.skip_newlines();
+        }
+        self.expect(Token::CloseBrace, "expected '}'")?;
+        Ok(Expr::Block(expression))
+    }
+    // eats `keyword` when it continues the statement, possibly on the line after a closing brace
+    fn eat_clause(&mut self, keyword: Token) -> bool {
+        let mut ahead = self.current;
+        while self.tokens.get(ahead) == Some(&Token::Newline) {
+            ahead += 1;
+        }
+        if self.tokens.get(ahead) != Some(&keyword) {
+            return false;
+        }
+        self.current = ahead + 1;
+        true
+    }
+    fn parse_else(&mut self) -> PResult<Expr> {
+        if !self.eat_clause(Token::Else) {
+            return Ok(Expr::Literal(Value::None));
+        }
+        if *self.at() == Token::If {
+            self.nud()
+        } else {
+            self.parse_block()
+        }
+    }
+    fn parse_params(&mut self) -> PResult<Vec<Pattern>> {
+        self.expect(Token::OpenParen, "expected '(' before parameters")?;
+        let mut params = Vec::new();
+        while *self.at() != Token::CloseParen {
+            params.push(self.parse_pattern()?);
+            if *self.at() == Token::Comma {
+                self.eat();
+            }
+        }
+        self.expect(Token::CloseParen, "expected ')' after parameters")?;
+        Ok(params)
+    }
+    // the token right after the bracketed group that starts at the current token
+    fn after_group(&self) -> Option<&Token> {
+        let mut depth = 0;
+        for (ahead, token) in self.tokens.iter().enumerate().skip(self.current) {
+            match token {
+                match_open_groupers!() => depth += 1,
+                match_close_groupers!() => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.tokens.get(ahead + 1);
+                    }
+                }
+                Token::Eof => break,
+                _ => {}
+            }
+        }
+        None
+    }
+    // `[a, b] = ...` and `{name} = ...` destructure instead of building a list or map
+    fn at_destructure(&self) -> bool {
+        self.after_group() == Some(&Token::Equals)
+    }
+    // `{}` and `{key: ...` start a map literal rather than a block
+    fn at_map(&self) -> bool {
+        let mut ahead = self.current + 1;
+        while self.tokens.get(ahead) == Some(&Token::Newline) {
+            ahead += 1;
+        }
+        match self.tokens.get(ahead) {
+            Some(Token::CloseBrace) => ahead == self.current + 1,
+            Some(Token::Identifier(_) | Token::String(_) | Token::ShellWord(_)) => {
+                self.tokens.get(ahead + 1) == Some(&Token::Colon)
+            }
+            _ => false,
+        }
+    }
+    // the comma and newlines between list items or map entries
+    fn skip_separator(&mut self, close: Token) -> PResult<()> {
+        self.skip_newlines();
+        if *self.at() == Token::Comma {
+            self.eat();
+            self.skip_newlines();
+        } else if *self.at() != close {
+            return Err(self.error(format!(
+                "expected ',' or {close:?}, found {}",
+                shown(self.at())
+            )));
+        }
+        Ok(())
+    }
+    fn parse_pattern(&mut self) -> PResult<Pattern> {
+        Ok(match self.eat().clone() {
+            Token::Identifier(name) if name == "_" => Pattern::Wildcard,
+            Token::Identifier(name) => Pattern::Binding(name),
+            Token::Mul => Pattern::Glob("*".to_string()),
+            Token::ShellWord(word) => match word.strip_prefix("...") {
+                Some(name) => Pattern::Rest(name.to_string()),
+                None => Pattern::Glob(word),
+            },
+            Token::Matches => match self.eat().clone() {
+                Token::String(pattern) => {
+                    Pattern::Regex(Box::new(Expr::Literal(Value::String(pattern))))
+                }
+                other => {
+                    return Err(self.error(format!(
+                        "expected a regex string after '=~', found {}",
+                        shown(&other)
+                    )));
+                }
+            },
+            Token::Sub => Pattern::Value(Box::new(Expr::UnaryOp {
+                op: UnaryOp::Neg,
+                expr: Box::new(self.nud()?),
+            })),
+            Token::Int(_) | Token::Float(_) | Token::Bool(_) | Token::String(_) => {
+                self.current -= 1;
+                Pattern::Value(Box::new(self.nud()?))
+            }
+            Token::OpenBracket => {
+                let mut items = Vec::new();
+                self.skip_newlines();
+                while *self.at() != Token::CloseBracket {
+                    items.push(self.parse_pattern()?);
+                    self.skip_separator(Token::CloseBracket)?;
+                }
+                self.expect(Token::CloseBracket, "expected ']' after list pattern")?;
+                Pattern::List(items)
+            }
+            Token::OpenBrace => {
+                // `{name}` is short for `{name: name}`
+                let mut entries = Vec::new();
+                self.skip_newlines();
+                while *self.at() != Token::CloseBrace {
+                    let key = match self.eat().clone() {
+                        Token::Identifier(key) | Token::String(key) | Token::ShellWord(key) => key,
+                        other => {
+                            return Err(
+                                self.error(format!("expected a map key, found {}", shown(&other)))
+                            );
+                        }
+                    };
+                    let pattern = if *self.at() == Token::Colon {
+                        self.eat();
+                        self.parse_pattern()?
+                    } else {
+                        Pattern::Binding(key.clone())
+                    };
+                    entries.push((key, pattern));
+                    self.skip_separator(Token::CloseBrace)?;
+                }
+                self.expect(Token::CloseBrace, "expected '}' after map pattern")?;
+                Pattern::Map(entries)
+            }
+            other => {
+                return Err(self.error(format!("expected a pattern, found {}", shown(&other))));
+            }
+        })
+    }
+    fn parse_identifier(&mut self, err: &str) -> PResult<String> {
+        match self.eat().clone() {
+            Token::Identifier(name) => Ok(name),
+            other => Err(self.error(format!("{err}, found {}", shown(&other)))),
+        }
+    }
+    fn collect_args(&mut self, prec: Precedence) -> PResult<Vec<Expr>> {
         let mut args = Vec::new();
         self.eat();
         while self.is_arg() {
             if self.get_precedence() < prec {
                 break;
             }
-            args.push(self.parse_expression(prec));
+            args.push(self.parse_expression(prec)?);
+        }
+        Ok(args)
+    }
+    // where a word starts a command, so an alias there is expanded: not after an operator or
+    // in an argument list, and not when it's being assigned, called, indexed or looped over
+    fn at_command_position(&self) -> bool {
+        let starts = matches!(
+            self.peek_prev(),
+            None | Some(
+                Token::Newline
+                    | Token::AndThen
+                    | Token::OrElse
+                    | Token::Pipe
+                    | Token::Background
+                    | Token::If
+                    | Token::While
+                    | Token::Else
+                    | Token::OpenBrace
+                    | Token::Arrow
+            )
+        );
+        starts
+            && !matches!(
+                self.tokens.get(self.current + 1),
+                Some(
+                    Token::Equals
+                        | Token::AddAssign
+                        | Token::SubAssign
+                        | Token::MulAssign
+                        | Token::DivAssign
+                        | Token::OpenParen
+                        | Token::OpenBracket
+                        | Token::Colon
+                        | Token::In
+                )
+            )
+    }
+    // splices in the tokens of an alias at command position, again for an alias its value
+    // starts with, but never twice for one name, so `alias ls = "ls -F"` stops at `ls`
+    fn expand_alias(&mut self) {
+        let mut expanded = HashSet::new();
+        while let Token::Identifier(word) | Token::ShellWord(word) = self.at()
+            && let Some(tokens) = self.aliases.get(word)
+            && self.at_command_position()
+            && expanded.insert(word.clone())
+        {
+            let tokens = tokens.clone();
+            let line = self.line();
+            let count = tokens.len();
+            self.tokens.splice(self.current..=self.current, tokens);
+            self.lines.splice(
+                self.current..=self.current,
+                std::iter::repeat_n(line, count),
+            );
         }
-        args
     }
-    fn parse_expression(&mut self, precedence: Precedence) -> Expr {
-        let mut left = self.nud();
+    // `alias ll = "ls -la"` and `abbr gs = "git status"` take the name and value apart, the
+    // value being any expression
+    fn parse_definition(&mut self, command: String) -> PResult<Expr> {
+        self.eat();
+        let name = match self.eat().clone() {
+            Token::Identifier(name) | Token::ShellWord(name) => name,
+            other => {
+                return Err(self.error(format!(
+                    "expected a name after {command}, found {}",
+                    shown(&other)
+                )));
+            }
+        };
+        self.expect(Token::Equals, "expected '=' after the name")?;
+        let value = self.parse_expression(Precedence::Assignment)?;
+        Ok(Expr::CommandCall {
+            command: Box::new(Expr::Identifier(command)),
+            args: vec![
+                Expr::Literal(Value::String(name)),
+                Expr::Literal(Value::String("=".to_string())),
+                value,
+            ],
+        })
+    }
+    fn parse_expression(&mut self, precedence: Precedence) -> PResult<Expr> {
+        let mut left = self.nud()?;
 
         while precedence < self.get_precedence() || precedence == Precedence::CallOrIndex {
-            left = self.led(left);
+            left = self.led(left)?;
         }
-        left
+        Ok(left)
     }
-    fn nud(&mut self) -> Expr {
-        match self.at().clone() {
-            Token::Number(x) => {
+    fn nud(&mut self) -> PResult<Expr> {
+        if !self.aliases.is_empty() {
+            self.expand_alias();
+        }
+        Ok(match self.at().clone() {
+            Token::Int(x) => {
+                self.eat();
+                Expr::Literal(Value::Int(x))
+            }
+            Token::Float(x) => {
                 self.eat();
-                Expr::Literal(Value::Number(x.to_owned()))
+                Expr::Literal(Value::Float(x))
             }
             Token::String(x) => {
                 self.eat();
@@ -184,16 +612,52 @@ impl Parser {
                     Bool::False => false,
                 }))
             }
+            Token::Identifier(x)
+                if (x == "alias" || x == "abbr")
+                    && self.tokens.get(self.current + 2) == Some(&Token::Equals) =>
+            {
+                self.parse_definition(x)?
+            }
+            // `trap { echo bye } EXIT`: a block right after the word starting a command line is
+            // a function passed to that command
+            Token::Identifier(x)
+                if self.tokens.get(self.current + 1) == Some(&Token::OpenBrace)
+                    && matches!(
+                        self.peek_prev(),
+                        None | Some(
+                            Token::Newline
+                                | Token::AndThen
+                                | Token::OrElse
+                                | Token::Background
+                                | Token::OpenBrace
+                        )
+                    ) =>
+            {
+                self.eat();
+                let body = self.parse_block()?;
+                let mut args = vec![Expr::Lambda {
+                    params: Vec::new(),
+                    body: Rc::new(body),
+                }];
+                while self.is_arg() {
+                    args.push(self.parse_expression(Precedence::CommandArg)?);
+                }
+                Expr::CommandCall {
+                    command: Box::new(Expr::Identifier(x)),
+                    args,
+                }
+            }
             Token::Identifier(x) => {
-                if let Some(match_literals!(_)) = self.peek_next() {
+                if self.peek_next().is_some_and(starts_arg) {
                     if let Some(prev) = self.peek_prev() {
                         match prev {
                             Token::Newline
                             | match_shell_ops!()
                             | match_keywords!()
                             | match_open_groupers!()
-                            | Token::Comma => {
-                                let args = self.collect_args(Precedence::CommandArg);
+                            | Token::Comma
+                            | Token::Arrow => {
+                                let args = self.collect_args(Precedence::CommandArg)?;
 
                                 Expr::CommandCall {
                                     command: Box::new(Expr::Identifier(x)),
@@ -206,7 +670,7 @@ impl Parser {
                             }
                         }
                     } else {
-                        let args = self.collect_args(Precedence::CommandArg);
+                        let args = self.collect_args(Precedence::CommandArg)?;
                         Expr::CommandCall {
                             command: Box::new(Expr::Identifier(x)),
                             args,
@@ -218,15 +682,16 @@ impl Parser {
                 }
             }
             Token::ShellWord(x) => {
-                if let Some(match_literals!(_)) = self.peek_next() {
+                if self.peek_next().is_some_and(starts_arg) {
                     if let Some(prev) = self.peek_prev() {
                         match prev {
                             Token::Newline
                             | match_shell_ops!()
                             | match_keywords!()
                             | match_open_groupers!()
-                            | Token::Comma => {
-                                let args = self.collect_args(Precedence::CommandArg);
+                            | Token::Comma
+                            | Token::Arrow => {
+                                let args = self.collect_args(Precedence::CommandArg)?;
                                 Expr::CommandCall {
                                     command: Box::new(Expr::ShellWord(x)),
                                     args,
@@ -238,7 +703,7 @@ impl Parser {
                             }
                         }
                     } else {
-                        let args = self.collect_args(Precedence::CommandArg);
+                        let args = self.collect_args(Precedence::CommandArg)?;
                         Expr::CommandCall {
                             command: Box::new(Expr::ShellWord(x)),
                             args,
@@ -249,52 +714,257 @@ impl Parser {
                     Expr::ShellWord(x)
                 }
             }
-            Token::Function => todo!(),
-            Token::If => todo!(),
-            Token::While => todo!(),
-            Token::For => todo!(),
-            Token::OpenParen => {
+            Token::Function => {
                 self.eat();
-                let expr = self.parse_expression(Precedence::Lowest);
-                self.expect(Token::CloseParen, "Expected ')' after expression");
-                expr
+                if *self.at() == Token::OpenParen {
+                    let params = self.parse_params()?;
+                    let body = self.parse_block()?;
+                    return Ok(Expr::Lambda {
+                        params,
+                        body: Rc::new(body),
+                    });
+                }
+                let name = self.parse_identifier("expected a function name")?;
+                let params = self.parse_params()?;
+                let body = self.parse_block()?;
+                Expr::FuncDef {
+                    name,
+                    params,
+                    body: Box::new(body),
+                }
             }
-            Token::OpenBrace => {
-                let mut expression = Vec::new();
+            Token::If => {
+                self.eat();
+                let cond = self.parse_expression(Precedence::Lowest)?;
+                let then_branch = self.parse_block()?;
+                let else_branch = self.parse_else()?;
+                Expr::If {
+                    cond: Box::new(cond),
+                    then_branch: Box::new(then_branch),
+                    else_branch: Box::new(else_branch),
+                }
+            }
+            Token::While => {
+                self.eat();
+                let cond = self.parse_expression(Precedence::Lowest)?;
+                let body = self.parse_block()?;
+                Expr::While {
+                    cond: Box::new(cond),
+                    body: Box::new(body),
+                }
+            }
+            Token::Return => {
+                self.eat();
+                let value = match self.at() {
+                    Token::Newline | match_close_groupers!() | Token::Eof => {
+                        Expr::Literal(Value::None)
+                    }
+                    _ => self.parse_expression(Precedence::Lowest)?,
+                };
+                Expr::Return(Box::new(value))
+            }
+            Token::Let => {
                 self.eat();
-                if *self.at() == Token::Newline {
+                let name = self.parse_identifier("expected a variable name after 'let'")?;
+                let value = if *self.at() == Token::Equals {
                     self.eat();
+                    self.parse_expression(Precedence::Lowest)?
+                } else {
+                    Expr::Literal(Value::None)
+                };
+                Expr::Let {
+                    name,
+                    value: Box::new(value),
                 }
-
+            }
+            Token::Match => {
+                self.eat();
+                let subject = self.parse_expression(Precedence::Lowest)?;
+                self.expect(Token::OpenBrace, "expected '{' after match subject")?;
+                let mut arms = Vec::new();
+                self.skip_newlines();
                 while *self.at() != Token::CloseBrace {
-                    let expr = self.parse_expression(Precedence::Lowest);
-                    expression.push(expr);
-
-                    if *self.at() == Token::Newline {
+                    let pattern = self.parse_pattern()?;
+                    let guard = if *self.at() == Token::If {
+                        self.eat();
+                        Some(self.parse_expression(Precedence::Lowest)?)
+                    } else {
+                        None
+                    };
+                    self.expect(Token::Arrow, "expected '=>' after match pattern")?;
+                    let body = self.parse_expression(Precedence::Lowest)?;
+                    arms.push(MatchArm {
+                        pattern,
+                        guard,
+                        body,
+                    });
+                    // arms end at a newline or a comma
+                    if *self.at() == Token::Comma {
                         self.eat();
                     }
+                    self.skip_newlines();
+                }
+                self.expect(Token::CloseBrace, "expected '}' after match arms")?;
+                Expr::Match {
+                    subject: Box::new(subject),
+                    arms,
+                }
+            }
+            Token::Try => {
+                self.eat();
+                let body = self.parse_block()?;
+                let (binding, handler) = if self.eat_clause(Token::Catch) {
+                    let binding = match self.at() {
+                        // `catch {message} {` binds with a map pattern, `catch {` has no binding
+                        Token::OpenBrace if self.after_group() != Some(&Token::OpenBrace) => None,
+                        _ => Some(self.parse_pattern()?),
+                    };
+                    (binding, Some(Box::new(self.parse_block()?)))
+                } else {
+                    (None, None)
+                };
+                let finally = match self.eat_clause(Token::Finally) {
+                    true => Some(Box::new(self.parse_block()?)),
+                    false => None,
+                };
+                if handler.is_none() && finally.is_none() {
+                    return Err(
+                        self.error("expected 'catch' or 'finally' after a try block".into())
+                    );
+                }
+                Expr::Try {
+                    body: Box::new(body),
+                    binding,
+                    handler,
+                    finally,
+                }
+            }
+            Token::Throw => {
+                self.eat();
+                let value = self.parse_expression(Precedence::Lowest)?;
+                Expr::Throw(Box::new(value))
+            }
+            Token::For => {
+                self.eat();
+                let pattern = self.parse_pattern()?;
+                self.expect(Token::In, "expected 'in' after loop variable")?;
+                let iterable = self.parse_expression(Precedence::Lowest)?;
+                let body = self.parse_block()?;
+                Expr::For {
+                    pattern,
+                    iterable: Box::new(iterable),
+                    body: Box::new(body),
+                }
+            }
+            Token::ProcessIn | Token::ProcessOut => {
+                let output = *self.eat() == Token::ProcessOut;
+                let line = self.line();
+                let body = match self.nud()? {
+                    Expr::Subshell(body) => body,
+                    expr => vec![Expr::Statement {
+                        line,
+                        expr: Box::new(expr),
+                    }],
+                };
+                Expr::ProcessSubstitution { body, output }
+            }
+            // parentheses group an expression, but around several statements, or one that
+            // could change the shell, they make a subshell
+            Token::OpenParen => {
+                let statement = matches!(
+                    self.peek_prev(),
+                    None | Some(
+                        Token::Newline
+                            | Token::AndThen
+                            | Token::OrElse
+                            | Token::Pipe
+                            | Token::Background
+                            | Token::OpenBrace
+                            | Token::OpenParen
+                    )
+                );
+                self.eat();
+                self.skip_newlines();
+                let mut body = vec![self.parse_statement()?];
+                self.skip_newlines();
+                while *self.at() != Token::CloseParen {
+                    body.push(self.parse_statement()?);
+                    self.skip_newlines();
+                }
+                self.expect(Token::CloseParen, "expected ')' after expression")?;
+                if let [Expr::Statement { expr, .. }] = body.as_slice()
+                    && !isolates(expr, statement)
+                {
+                    return Ok((**expr).clone());
+                }
+                Expr::Subshell(body)
+            }
+            Token::OpenBracket | Token::OpenBrace if self.at_destructure() => {
+                let pattern = self.parse_pattern()?;
+                self.expect(Token::Equals, "expected '=' after destructuring pattern")?;
+                let value = self.parse_expression(Precedence::Lowest)?;
+                Expr::Destructure {
+                    pattern,
+                    value: Box::new(value),
+                }
+            }
+            Token::OpenBrace if self.at_map() => {
+                self.eat();
+                let mut entries = Vec::new();
+                self.skip_newlines();
+                while *self.at() != Token::CloseBrace {
+                    let key = match self.eat().clone() {
+                        Token::Identifier(key) | Token::String(key) | Token::ShellWord(key) => key,
+                        other => {
+                            return Err(
+                                self.error(format!("expected a map key, found {}", shown(&other)))
+                            );
+                        }
+                    };
+                    self.expect(Token::Colon, "expected ':' after map key")?;
+                    entries.push((key, self.parse_expression(Precedence::Lowest)?));
+                    self.skip_separator(Token::CloseBrace)?;
+                }
+                self.expect(Token::CloseBrace, "expected '}' after map entries")?;
+                Expr::Map(entries)
+            }
+            Token::OpenBrace => self.parse_block()?,
+            Token::OpenBracket => {
+                self.eat();
+                let mut items = Vec::new();
+                self.skip_newlines();
+                while *self.at() != Token::CloseBracket {
+                    items.push(self.parse_expression(Precedence::Lowest)?);
+                    self.skip_separator(Token::CloseBracket)?;
+                }
+                self.expect(Token::CloseBracket, "expected ']' after list items")?;
+                Expr::List(items)
+            }
+            Token::Sub => {
+                self.eat();
+                let right = self.parse_expression(Precedence::Unary)?;
+                Expr::UnaryOp {
+                    op: UnaryOp::Neg,
+                    expr: Box::new(right),
                 }
-                self.expect(Token::CloseBrace, "Expected closing brace");
-                Expr::Block(expression)
             }
-            Token::OpenBracket => todo!(),
             Token::Not => {
                 self.eat();
-                let right = self.parse_expression(Precedence::Unary);
+                let right = self.parse_expression(Precedence::Unary)?;
                 Expr::UnaryOp {
                     op: UnaryOp::Not,
                     expr: Box::new(right),
                 }
             }
-            _ => panic!("Unexpected token in nud: {:?}", self.at()),
-        }
+            _ => return Err(self.unexpected()),
+        })
     }
-    fn led(&mut self, left: Expr) -> Expr {
+    fn led(&mut self, left: Expr) -> PResult<Expr> {
         macro_rules! return_op {
             ($x:expr) => {{
                 let prec = self.get_precedence();
                 self.eat();
-                let right = self.parse_expression(prec.next_higher());
+                let right = self.parse_expression(prec.next_higher())?;
                 Expr::BinaryOp {
                     left: Box::new(left),
                     op: $x,
@@ -302,12 +972,26 @@ impl Parser {
                 }
             }};
         }
+        macro_rules! return_redirect {
+            ($fd:expr, $x:path) => {{
+                let prec = self.get_precedence();
+                self.eat();
+                let target = self.parse_expression(prec.next_higher())?;
+                redirect(
+                    left,
+                    vec![Redirection {
+                        fd: $fd,
+                        kind: $x(target),
+                    }],
+                )
+            }};
+        }
         let token = self.at().clone();
-        match token {
+        Ok(match token {
             Token::AndThen => {
                 let prec = self.get_precedence();
                 self.eat();
-                let right = self.parse_expression(prec.next_higher());
+                let right = self.parse_expression(prec.next_higher())?;
                 Expr::AndThen {
                     left: Box::new(left),
                     right: Box::new(right),
@@ -316,19 +1000,134 @@ impl Parser {
             Token::OrElse => {
                 let prec = self.get_precedence();
                 self.eat();
-                let right = self.parse_expression(prec.next_higher());
+                let right = self.parse_expression(prec.next_higher())?;
                 Expr::OrElse {
                     left: Box::new(left),
                     right: Box::new(right),
                 }
             }
+            Token::Pipe => {
+                let prec = self.get_precedence();
+                self.eat();
+                let right = self.parse_expression(prec.next_higher())?;
+                Expr::Pipe {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }
+            }
+            Token::RedirectIn => return_redirect!(0, RedirectKind::In),
+            Token::RedirectOut => return_redirect!(1, RedirectKind::Out),
+            Token::RedirectOutAppend => return_redirect!(1, RedirectKind::Append),
+            Token::HereString => return_redirect!(0, RedirectKind::HereString),
+            Token::HereDoc(body) => {
+                self.eat();
+                redirect(
+                    left,
+                    vec![Redirection {
+                        fd: 0,
+                        kind: RedirectKind::HereDoc(body),
+                    }],
+                )
+            }
+            Token::RawHereDoc(body) => {
+                self.eat();
+                redirect(
+                    left,
+                    vec![Redirection {
+                        fd: 0,
+                        kind: RedirectKind::RawHereDoc(body),
+                    }],
+                )
+            }
+            Token::FdRedirect(word) => {
+                let prec = self.get_precedence();
+                self.eat();
+                let (fd, op, target) = split_fd_redirect(&word).unwrap();
+                let number = match fd {
+                    "" if op == "<-" => 0,
+                    "" | "&" => 1,
+                    _ => fd.parse().unwrap(),
+                };
+                let null = || Expr::Literal(Value::String("/dev/null".to_string()));
+                let kind = match target {
+                    "&-" => RedirectKind::Close,
+                    _ if target.starts_with('&') => RedirectKind::Dup(target[1..].parse().unwrap()),
+                    "null" if op == "<-" => RedirectKind::In(null()),
+                    "null" => RedirectKind::Out(null()),
+                    _ => {
+                        let target = self.parse_expression(prec.next_higher())?;
+                        match op {
+                            "<-" => RedirectKind::In(target),
+                            ">>" => RedirectKind::Append(target),
+                            _ => RedirectKind::Out(target),
+                        }
+                    }
+                };
+                let mut redirects = vec![Redirection { fd: number, kind }];
+                // `&->` sends stderr wherever stdout now goes
+                if fd == "&" {
+                    redirects.push(Redirection {
+                        fd: 2,
+                        kind: RedirectKind::Dup(1),
+                    });
+                }
+                redirect(left, redirects)
+            }
             Token::Add => return_op!(BinaryOp::Add),
             Token::Sub => return_op!(BinaryOp::Sub),
             Token::Mul => return_op!(BinaryOp::Mul),
             Token::Div => return_op!(BinaryOp::Div),
             Token::Mod => return_op!(BinaryOp::Mod),
+            Token::FloorDiv => return_op!(BinaryOp::FloorDiv),
+            Token::BitAnd => return_op!(BinaryOp::BitAnd),
+            Token::BitOr => return_op!(BinaryOp::BitOr),
+            Token::BitXor => return_op!(BinaryOp::BitXor),
+            Token::ShiftLeft => return_op!(BinaryOp::Shl),
+            Token::ShiftRight => return_op!(BinaryOp::Shr),
+            Token::Pow => {
+                self.eat();
+                // parsing the right side one level lower makes `2 ** 3 ** 2` group to the right
+                let right = self.parse_expression(Precedence::Unary)?;
+                Expr::BinaryOp {
+                    left: Box::new(left),
+                    op: BinaryOp::Pow,
+                    right: Box::new(right),
+                }
+            }
             Token::EqualTo => return_op!(BinaryOp::Eq),
             Token::NotEqualTo => return_op!(BinaryOp::Neq),
+            Token::Matches => return_op!(BinaryOp::Matches),
+            Token::In => {
+                self.eat();
+                // parsed at its own level so `x in 1..10` takes the whole range
+                let right = self.parse_expression(Precedence::Comparison)?;
+                Expr::BinaryOp {
+                    left: Box::new(left),
+                    op: BinaryOp::In,
+                    right: Box::new(right),
+                }
+            }
+            Token::DotDot | Token::DotDotEqual => {
+                let inclusive = token == Token::DotDotEqual;
+                self.eat();
+                let end = self.parse_expression(Precedence::Range.next_higher())?;
+                // `step` is only special right after a range, so it stays usable as a name
+                let step = match self.at() {
+                    Token::Identifier(word) if word == "step" => {
+                        self.eat();
+                        Some(Box::new(
+                            self.parse_expression(Precedence::Range.next_higher())?,
+                        ))
+                    }
+                    _ => None,
+                };
+                Expr::Range {
+                    start: Box::new(left),
+                    end: Box::new(end),
+                    step,
+                    inclusive,
+                }
+            }
             Token::LessThan => return_op!(BinaryOp::Lt),
             Token::GreaterThan => return_op!(BinaryOp::Gt),
             Token::LessThanEqual => return_op!(BinaryOp::Le),
@@ -336,45 +1135,60 @@ impl Parser {
             Token::And => return_op!(BinaryOp::And),
             Token::Or => return_op!(BinaryOp::Or),
             Token::Equals => {
-                if let Expr::Identifier(name) = left {
-                    self.eat();
-                    let value = self.parse_expression(Precedence::Lowest);
-                    Expr::Assign {
-                        name,
-                        value: Box::new(value),
-                    }
-                } else {
-                    panic!("Invalid assignment target")
-                }
+                self.eat();
+                let value = self.parse_expression(Precedence::Lowest)?;
+                self.assignment(left, value)?
+            }
+            Token::AddAssign | Token::SubAssign | Token::MulAssign | Token::DivAssign => {
+                let op = match token {
+                    Token::AddAssign => BinaryOp::Add,
+                    Token::SubAssign => BinaryOp::Sub,
+                    Token::MulAssign => BinaryOp::Mul,
+                    _ => BinaryOp::Div,
+                };
+                self.eat();
+                let value = self.parse_expression(Precedence::Lowest)?;
+                // `x += 1` is `x = x + 1`
+                let value = Expr::BinaryOp {
+                    left: Box::new(left.clone()),
+                    op,
+                    right: Box::new(value),
+                };
+                self.assignment(left, value)?
             }
             Token::OpenParen => {
                 self.eat();
                 let mut args = Vec::new();
                 if *self.at() != Token::CloseParen {
                     loop {
-                        args.push(self.parse_expression(Precedence::Lowest));
+                        args.push(self.parse_expression(Precedence::Lowest)?);
                         match self.at() {
                             Token::Comma => {
                                 self.eat();
                             }
                             Token::CloseParen => break,
-                            other => panic!("Unexpected token {:?} in argument list", other),
+                            _ => return Err(self.unexpected()),
                         }
                     }
                 }
-                self.expect(Token::CloseParen, "Expected ')' after function arguments.");
+                self.expect(Token::CloseParen, "expected ')' after function arguments")?;
                 Expr::Call {
                     callee: Box::new(left),
                     args,
                 }
             }
-            Token::Number(_) => todo!(),
-            Token::String(_) => todo!(),
-            Token::Identifier(_) => todo!(),
-            Token::OpenBracket => todo!(), // indexing
-            Token::Comma => todo!(),
-            _ => panic!("Unexpected token in led: {:?}", self.at()),
-        }
+            Token::OpenBracket => {
+                self.eat();
+                let index = self.parse_expression(Precedence::Lowest)?;
+                self.expect(Token::CloseBracket, "expected ']' after index")?;
+                Expr::Index {
+                    target: Box::new(left),
+                    index: Box::new(index),
+                }
+            }
+            // a literal or comma right after a finished expression, as in `f = 3 4`
+            _ => return Err(self.unexpected()),
+        })
     }
     fn get_precedence(&self) -> Precedence {
         self.get_token_precedence(self.at())
@@ -385,25 +1199,44 @@ impl Parser {
             Token::OrElse => OrElse,
             Token::AndThen => AndThen,
 
-            Token::Equals => Assignment,
+            Token::Equals
+            | Token::AddAssign
+            | Token::SubAssign
+            | Token::MulAssign
+            | Token::DivAssign => Assignment,
             Token::Pipe => Pipe,
 
-            Token::RedirectIn | Token::RedirectOut | Token::RedirectOutAppend => Redirect,
+            Token::RedirectIn
+            | Token::RedirectOut
+            | Token::RedirectOutAppend
+            | Token::FdRedirect(_)
+            | Token::HereString
+            | Token::HereDoc(_)
+            | Token::RawHereDoc(_) => Redirect,
 
-            match_literals!(_) => CommandArg,
+            match_literals!(_) | Token::ProcessIn | Token::ProcessOut => CommandArg,
 
             Token::Or => Or,
             Token::And => And,
 
-            Token::EqualTo | Token::NotEqualTo => Equality,
+            Token::EqualTo | Token::NotEqualTo | Token::Matches => Equality,
 
             Token::GreaterThan
             | Token::GreaterThanEqual
             | Token::LessThan
-            | Token::LessThanEqual => Comparison,
+            | Token::LessThanEqual
+            | Token::In => Comparison,
+
+            Token::DotDot | Token::DotDotEqual => Range,
+
+            Token::BitOr => BitOr,
+            Token::BitXor => BitXor,
+            Token::BitAnd => BitAnd,
+            Token::ShiftLeft | Token::ShiftRight => Shift,
 
             Token::Add | Token::Sub => Term,
-            Token::Mul | Token::Div | Token::Mod => Factor,
+            Token::Mul | Token::Div | Token::FloorDiv | Token::Mod => Factor,
+            Token::Pow => Exponent,
 
             Token::OpenParen | Token::OpenBracket => CallOrIndex,
 
@@ -411,3 +1244,82 @@ impl Parser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::lexer::Lexer;
+
+    fn parse(source: &str) -> PResult<Vec<Expr>> {
+        let (tokens, lines) = Lexer::new().tokenize(source).unwrap();
+        let mut parser = Parser::new();
+        parser.load(tokens, lines);
+        let mut statements = Vec::new();
+        while let Some(statement) = parser.next_statement(HashMap::new())? {
+            match statement {
+                Expr::Statement { expr, .. } => statements.push(*expr),
+                other => statements.push(other),
+            }
+        }
+        Ok(statements)
+    }
+
+    #[test]
+    fn errors_have_lines() {
+        let err = parse("x = 1\nf = 3 4\n").unwrap_err();
+        assert_eq!(err.message, "unexpected 4");
+        assert_eq!(err.line, 2);
+        assert!(parse("if x {\n").is_err());
+        assert!(parse("x = )").is_err());
+    }
+
+    #[test]
+    fn subshells_and_grouping() {
+        assert!(matches!(
+            parse("(cd /tmp)").unwrap()[..],
+            [Expr::Subshell(_)]
+        ));
+        assert!(matches!(parse("(x = 1)").unwrap()[..], [Expr::Subshell(_)]));
+        assert!(matches!(
+            parse("y = (1 + 2) * 3").unwrap()[..],
+            [Expr::Assign { .. }]
+        ));
+    }
+
+    #[test]
+    fn assignment_targets() {
+        assert!(matches!(
+            parse("xs[0] = 5").unwrap()[..],
+            [Expr::IndexAssign { .. }]
+        ));
+        assert!(matches!(
+            parse("m[\"a\"][1] += 1").unwrap()[..],
+            [Expr::IndexAssign { .. }]
+        ));
+        let err = parse("f(1) = 2").unwrap_err();
+        assert_eq!(err.message, "invalid assignment target");
+    }
+
+    #[test]
+    fn block_arguments() {
+        let [Expr::CommandCall { args, .. }] = &parse("trap { echo bye } EXIT").unwrap()[..] else {
+            panic!("expected a command");
+        };
+        assert!(matches!(
+            args[..],
+            [Expr::Lambda { .. }, Expr::Identifier(_)]
+        ));
+        assert!(matches!(
+            parse("if ready { go }").unwrap()[..],
+            [Expr::If { .. }]
+        ));
+    }
+
+    #[test]
+    fn redirects() {
+        let [Expr::Redirect { redirects, .. }] = &parse("cmd 2->&1").unwrap()[..] else {
+            panic!("expected a redirect");
+        };
+        assert_eq!(redirects.len(), 1);
+    }
+}
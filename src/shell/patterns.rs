@@ -0,0 +1,271 @@
+use super::Shell;
+use super::ast::{Expr, MatchArm, Pattern};
+use super::interpreter::{EvalResult, Unwind, numeric_eq};
+use super::values::Value;
+use std::fs;
+use std::path::Path;
+
+// translates a glob (`*`, `?`, `[abc]`, `[!abc]`) into an anchored regex
+pub(super) fn glob_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' if chars.clone().any(|next| next == ']') => {
+                regex.push('[');
+                if chars.next_if(|next| *next == '!' || *next == '^').is_some() {
+                    regex.push('^');
+                }
+                for next in chars.by_ref() {
+                    if next == ']' {
+                        break;
+                    }
+                    if next == '\\' || next == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(next);
+                }
+                regex.push(']');
+            }
+            _ => regex.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+// true when a word has `*`, `?` or a closed `[...]` class to expand
+pub(super) fn is_glob(word: &str) -> bool {
+    word.contains(['*', '?'])
+        || word
+            .find('[')
+            .is_some_and(|open| word[open + 1..].contains(']'))
+}
+
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "" => name.to_string(),
+        _ if dir.ends_with('/') => format!("{dir}{name}"),
+        _ => format!("{dir}/{name}"),
+    }
+}
+
+// the entries of a directory by name, leaving out hidden ones unless asked for
+fn entries(dir: &str, hidden: bool) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| hidden || !name.starts_with('.'))
+        .collect()
+}
+
+// `dir` and everything below it for `**`; only directories unless the glob ends there
+fn descendants(dir: &str, files: bool, found: &mut Vec<String>) {
+    for name in entries(dir, false) {
+        let path = join(dir, &name);
+        // symlinked directories aren't followed, so cycles can't recurse forever
+        let is_dir = fs::symlink_metadata(&path).is_ok_and(|meta| meta.is_dir());
+        if is_dir || files {
+            found.push(path.clone());
+        }
+        if is_dir {
+            descendants(&path, files, found);
+        }
+    }
+}
+
+// how a pattern's names are bound: `let`-style in the current scope, or like `=`
+#[derive(Clone, Copy)]
+pub(super) enum Bind {
+    Declare,
+    Assign,
+}
+
+impl Shell {
+    // the sorted paths a glob matches, segment by segment: `*`, `?` and `[...]` stay within
+    // one path component while `**` spans any number of directories
+    pub(super) fn expand_glob(&mut self, glob: &str) -> Vec<String> {
+        let mut paths = vec![if glob.starts_with('/') { "/" } else { "" }.to_string()];
+        let segments: Vec<_> = glob.split('/').collect();
+        for (i, segment) in segments.iter().enumerate() {
+            let last = i + 1 == segments.len();
+            let mut next = Vec::new();
+            match *segment {
+                // `a//b` and the root's leading `/`; a trailing `/` only keeps directories
+                "" if last && i > 0 => next.extend(
+                    paths
+                        .iter()
+                        .filter(|path| Path::new(path).is_dir())
+                        .map(|path| join(path, "")),
+                ),
+                "" => next = paths,
+                "**" => {
+                    for path in &paths {
+                        if !last {
+                            next.push(path.clone());
+                        }
+                        descendants(path, last, &mut next);
+                    }
+                }
+                _ if is_glob(segment) => {
+                    let Ok(regex) = self.regex(&glob_regex(segment)) else {
+                        return Vec::new();
+                    };
+                    for path in &paths {
+                        for name in entries(path, segment.starts_with('.')) {
+                            if regex.is_match(&name) {
+                                next.push(join(path, &name));
+                            }
+                        }
+                    }
+                }
+                _ => next.extend(
+                    paths
+                        .iter()
+                        .map(|path| join(path, segment))
+                        .filter(|path| fs::symlink_metadata(path).is_ok()),
+                ),
+            }
+            paths = next;
+        }
+        paths.retain(|path| !path.is_empty());
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+    pub(super) fn eval_match(&mut self, subject: &Expr, arms: &[MatchArm]) -> EvalResult {
+        let value = self.eval(subject)?;
+        for arm in arms {
+            // bindings from the pattern only live for its guard and body
+            self.env.push_scope();
+            let result = self.eval_arm(arm, &value);
+            self.env.pop_scope();
+            if let Some(result) = result? {
+                return Ok(result);
+            }
+        }
+        let shown = match &value {
+            Value::String(text) => format!("{text:?}"),
+            other => other.to_string(),
+        };
+        Err(Unwind::error(
+            "match",
+            format!("match: no arm matches {shown}"),
+        ))
+    }
+    fn eval_arm(&mut self, arm: &MatchArm, value: &Value) -> Result<Option<Value>, Unwind> {
+        if !self.match_pattern(&arm.pattern, value, Bind::Declare)? {
+            return Ok(None);
+        }
+        if let Some(guard) = &arm.guard
+            && !self.eval(guard)?.is_truthy()
+        {
+            return Ok(None);
+        }
+        self.eval_statement(&arm.body).map(Some)
+    }
+    // binds `value` to an assignment target, loop variable or parameter, which must fit
+    pub(super) fn destructure(
+        &mut self,
+        pattern: &Pattern,
+        value: &Value,
+        bind: Bind,
+    ) -> Result<(), Unwind> {
+        if self.match_pattern(pattern, value, bind)? {
+            return Ok(());
+        }
+        let shown = match value {
+            Value::String(text) => format!("{text:?}"),
+            other => other.to_string(),
+        };
+        Err(Unwind::error(
+            "match",
+            format!("cannot destructure the {} {shown}", value.type_name()),
+        ))
+    }
+    fn bind(&mut self, name: &str, value: Value, bind: Bind) {
+        match bind {
+            Bind::Declare => self.env.declare(name, value),
+            Bind::Assign => self.env.set(name, value),
+        }
+    }
+    fn match_pattern(
+        &mut self,
+        pattern: &Pattern,
+        value: &Value,
+        bind: Bind,
+    ) -> Result<bool, Unwind> {
+        match (pattern, value) {
+            (Pattern::Wildcard, _) => Ok(true),
+            (Pattern::Binding(name), value) => {
+                self.bind(name, value.clone(), bind);
+                Ok(true)
+            }
+            (Pattern::Value(expr), value) => Ok(numeric_eq(&self.eval(expr)?, value)),
+            (Pattern::Glob(glob), Value::String(text)) => {
+                let glob = self.interpolate(glob)?;
+                Ok(self.regex(&glob_regex(&glob))?.is_match(text))
+            }
+            (Pattern::Regex(expr), Value::String(text)) => {
+                let pattern = self.eval(expr)?.to_string();
+                Ok(self.match_regex(text, &pattern)?.is_truthy())
+            }
+            (Pattern::List(patterns), Value::List(items)) => {
+                // a `...rest` splits the patterns into those matched from the front and the back
+                let rest = patterns
+                    .iter()
+                    .position(|pattern| matches!(pattern, Pattern::Rest(_)));
+                let (front, back) = match rest {
+                    Some(i) => (&patterns[..i], &patterns[i + 1..]),
+                    None => (&patterns[..], &[][..]),
+                };
+                let fits = match rest {
+                    Some(_) => items.len() >= front.len() + back.len(),
+                    None => items.len() == front.len(),
+                };
+                if !fits {
+                    return Ok(false);
+                }
+                let tail = &items[items.len() - back.len()..];
+                for (pattern, item) in front.iter().zip(items).chain(back.iter().zip(tail)) {
+                    if !self.match_pattern(pattern, item, bind)? {
+                        return Ok(false);
+                    }
+                }
+                if let Some(Pattern::Rest(name)) = rest.map(|i| &patterns[i])
+                    && !name.is_empty()
+                    && name != "_"
+                {
+                    let middle = items[front.len()..items.len() - back.len()].to_vec();
+                    self.bind(name, Value::List(middle), bind);
+                }
+                Ok(true)
+            }
+            // `catch {message, kind}` reads an error's fields like map keys
+            (Pattern::Map(entries), Value::Error(err)) => {
+                for (key, pattern) in entries {
+                    match err.field(key) {
+                        Some(item) if self.match_pattern(pattern, &item, bind)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+            (Pattern::Map(entries), Value::Map(map)) => {
+                for (key, pattern) in entries {
+                    match map.get(key) {
+                        Some(item) if self.match_pattern(pattern, item, bind)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
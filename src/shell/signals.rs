@@ -0,0 +1,182 @@
+use super::Shell;
+use super::interpreter::{EvalResult, ShellError, Unwind};
+use super::values::{Function, Value};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+
+// one bit per signal that arrived while the shell itself was running, checked between statements
+static PENDING: AtomicU32 = AtomicU32::new(0);
+// the process group holding the terminal, if it isn't the shell's
+static FOREGROUND: AtomicI32 = AtomicI32::new(0);
+
+// what `trap` accepts, EXIT being the shell's own exit rather than a signal
+const TRAPS: [(&str, libc::c_int); 4] = [
+    ("EXIT", 0),
+    ("INT", libc::SIGINT),
+    ("TERM", libc::SIGTERM),
+    ("HUP", libc::SIGHUP),
+];
+
+// a signal meant for the foreground job goes to it; Ctrl-C with no job running, TERM and HUP
+// are left for the shell to act on. Only async-signal-safe calls belong here.
+extern "C" fn forward(signal: libc::c_int) {
+    let pgid = FOREGROUND.load(Ordering::Relaxed);
+    if pgid > 0 {
+        unsafe { libc::kill(-pgid, signal) };
+    }
+    let pending = match signal {
+        libc::SIGINT => pgid <= 0,
+        libc::SIGTERM | libc::SIGHUP => true,
+        _ => false,
+    };
+    if pending {
+        PENDING.fetch_or(1 << signal, Ordering::Relaxed);
+    }
+}
+
+// handles `signals` with `forward` instead of dying; without SA_RESTART, so a blocked read
+// at the prompt returns. Programs the shell runs start with the default handlers again.
+pub(super) fn catch(signals: &[libc::c_int]) {
+    for &signal in signals {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+pub(super) fn set_foreground(pgid: i32) {
+    FOREGROUND.store(pgid, Ordering::Relaxed);
+}
+
+// whether Ctrl-C arrived since the last call
+pub(super) fn take_interrupt() -> bool {
+    let bit = 1 << libc::SIGINT;
+    PENDING.fetch_and(!bit, Ordering::Relaxed) & bit != 0
+}
+
+pub(super) fn interrupt() {
+    PENDING.fetch_or(1 << libc::SIGINT, Ordering::Relaxed);
+}
+
+// `INT`, `SIGINT` or `int` to its name in `TRAPS`
+fn trap_name(name: &str) -> Option<&'static str> {
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    TRAPS
+        .iter()
+        .map(|(known, _)| *known)
+        .find(|known| *known == name)
+}
+
+impl Shell {
+    // acts on signals that arrived since the last statement: trapped ones run their handlers,
+    // Ctrl-C otherwise stops the running script or loop, and TERM or HUP end the shell
+    pub(super) fn check_signals(&mut self) -> Result<(), Unwind> {
+        let pending = PENDING.swap(0, Ordering::Relaxed);
+        for (name, signal) in TRAPS {
+            if signal == 0 || pending & (1 << signal) == 0 {
+                continue;
+            }
+            if self
+                .env
+                .traps
+                .get(name)
+                .is_some_and(|handlers| !handlers.is_empty())
+            {
+                self.run_traps(name)?;
+            } else if signal == libc::SIGINT {
+                return Err(Unwind::Error(ShellError {
+                    message: "interrupted".to_string(),
+                    kind: "interrupt".to_string(),
+                    status: 128 + signal,
+                    span: None,
+                    trace: Vec::new(),
+                }));
+            } else {
+                return Err(Unwind::Exit(128 + signal));
+            }
+        }
+        Ok(())
+    }
+    // runs `event`'s handlers in the order they were registered; an error in one is reported
+    // without skipping the rest, but `exit` in one ends the shell then and there
+    fn run_traps(&mut self, event: &str) -> Result<(), Unwind> {
+        let handlers = self.env.traps.get(event).cloned().unwrap_or_default();
+        for handler in handlers {
+            match self.call_function(&handler, Vec::new()) {
+                Err(Unwind::Error(err)) => self.report_error(&err),
+                Err(Unwind::Exit(code)) => return Err(Unwind::Exit(code)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    // the EXIT handlers, run once as the shell exits with `status`; they see it as `$?`
+    // and may change it with `exit`
+    pub(super) fn exit_traps(&mut self, status: i32) -> i32 {
+        if !self.env.traps.contains_key("EXIT") {
+            return status;
+        }
+        self.env.last_status = status;
+        let result = self.run_traps("EXIT");
+        self.env.traps.remove("EXIT");
+        match result {
+            Err(Unwind::Exit(code)) => code,
+            _ => status,
+        }
+    }
+    // trap(handler, "EXIT", "INT", ...), `trap cleanup TERM` or `trap { rm $tmp } EXIT`, adding
+    // the function, or the function of that name, after any already there; a handler of "-"
+    // clears them instead.
+    // With no arguments it returns the handlers by event.
+    pub(super) fn trap(&mut self, args: Vec<Value>) -> EvalResult {
+        let mut args = args.into_iter();
+        let Some(handler) = args.next() else {
+            let traps = self.env.traps.iter().map(|(event, handlers)| {
+                let handlers = handlers.iter().cloned().map(Value::Function).collect();
+                (event.clone(), Value::List(handlers))
+            });
+            return Ok(Value::Map(traps.collect::<BTreeMap<_, _>>()));
+        };
+        let handler: Option<Rc<Function>> = match handler {
+            Value::Function(function) => Some(function),
+            Value::String(name) if name == "-" => None,
+            Value::String(name) => match self.lookup(&name) {
+                Ok(Value::Function(function)) => Some(function),
+                _ => {
+                    return Err(Unwind::error(
+                        "name",
+                        format!("trap: no function named {name}"),
+                    ));
+                }
+            },
+            other => {
+                return Err(Unwind::error(
+                    "type",
+                    format!("trap expects a function, got a {}", other.type_name()),
+                ));
+            }
+        };
+        for event in args {
+            let event = event.to_string();
+            let Some(name) = trap_name(&event) else {
+                return Err(Unwind::error("value", format!("trap: cannot trap {event}")));
+            };
+            let handlers = self.env.traps.entry(name.to_string()).or_default();
+            match &handler {
+                Some(function) => handlers.push(function.clone()),
+                None => handlers.clear(),
+            }
+            if let Some((_, signal)) = TRAPS.iter().find(|(known, _)| *known == name)
+                && *signal != 0
+            {
+                catch(&[*signal]);
+            }
+        }
+        Ok(Value::None)
+    }
+}
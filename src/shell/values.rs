@@ -1,8 +1,203 @@
+use super::ast::{Expr, Pattern};
+use super::environment::Scope;
+use super::interpreter::ShellError;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
-    Number(f64),
+    Int(i64),
+    Float(f64),
     String(String),
     Boolean(bool),
-    None
-}
\ No newline at end of file
+    Status(i32), // exit status of a command, builtin or pipeline
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+    Range(Range),
+    Function(Rc<Function>),
+    Error(Rc<ShellError>), // a caught or constructed error
+    None,
+}
+
+// `start..end step n`, or `start..=end`; never materialized, so huge ranges cost nothing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64, // never zero
+    pub inclusive: bool,
+}
+
+impl Range {
+    // a u128 since `i64::MIN..=i64::MAX` holds one more item than a u64 can count
+    pub fn len(&self) -> u128 {
+        // i128 so spans across the whole i64 range can't overflow
+        let (start, step) = (self.start as i128, self.step as i128);
+        let mut end = self.end as i128;
+        if self.inclusive {
+            end += step.signum();
+        }
+        let span = if step > 0 { end - start } else { start - end };
+        if span <= 0 {
+            0
+        } else {
+            ((span + step.abs() - 1) / step.abs()) as u128
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // the item at `index`, counting back from the end when it's negative
+    pub fn get(&self, index: i64) -> Option<i64> {
+        let len = self.len() as i128;
+        let index = if index < 0 {
+            index as i128 + len
+        } else {
+            index as i128
+        };
+        (0..len).contains(&index).then(|| self.nth(index as u128))
+    }
+    pub fn contains(&self, x: i64) -> bool {
+        let offset = x as i128 - self.start as i128;
+        let step = self.step as i128;
+        offset % step == 0 && offset / step >= 0 && ((offset / step) as u128) < self.len()
+    }
+    pub fn iter(self) -> impl Iterator<Item = i64> {
+        (0..self.len()).map(move |index| self.nth(index))
+    }
+    // worked out in i128, where `index * step` can't overflow; the item itself always fits
+    fn nth(&self, index: u128) -> i64 {
+        (self.start as i128 + index as i128 * self.step as i128) as i64
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dots = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{dots}{}", self.start, self.end)?;
+        if self.step != 1 {
+            write!(f, " step {}", self.step)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: Option<String>, // `None` for lambdas
+    pub params: Vec<Pattern>,
+    pub body: Rc<Expr>,
+    pub captured: Vec<Scope>, // the defining environment
+    pub script: String,       // the file it was defined in, for error locations
+}
+
+// functions are equal only to themselves
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Int(x) => *x != 0,
+            Value::Float(x) => *x != 0.0,
+            Value::String(x) => !x.is_empty(),
+            Value::Boolean(x) => *x,
+            Value::Status(code) => *code == 0,
+            Value::List(items) => !items.is_empty(),
+            Value::Map(entries) => !entries.is_empty(),
+            Value::Range(range) => !range.is_empty(),
+            Value::Function(_) => true,
+            Value::Error(_) => true,
+            Value::None => false,
+        }
+    }
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "boolean",
+            Value::Status(_) => "status",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Range(_) => "range",
+            Value::Function(_) => "function",
+            Value::Error(_) => "error",
+            Value::None => "none",
+        }
+    }
+    // ints promote to floats when mixed with them
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(x) => Some(*x as f64),
+            Value::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                self.as_float()?.partial_cmp(&other.as_float()?)
+            }
+            (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+            _ => None,
+        }
+    }
+}
+
+// strings nested in lists and maps are quoted so element boundaries stay visible
+struct Nested<'a>(&'a Value);
+
+impl fmt::Display for Nested<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Value::String(x) => write!(f, "{x:?}"),
+            other => write!(f, "{other}"),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(x) => write!(f, "{x}"),
+            Value::Float(x) => write!(f, "{x:?}"),
+            Value::String(x) => write!(f, "{x}"),
+            Value::Boolean(x) => write!(f, "{x}"),
+            Value::Status(code) => write!(f, "{code}"),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", Nested(item))?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: {}", Nested(value))?;
+                }
+                write!(f, "}}")
+            }
+            Value::Range(range) => write!(f, "{range}"),
+            Value::Function(function) => match &function.name {
+                Some(name) => write!(f, "<function {name}>"),
+                None => write!(f, "<lambda>"),
+            },
+            Value::Error(err) => write!(f, "{}", err.message),
+            Value::None => Ok(()),
+        }
+    }
+}
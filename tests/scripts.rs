@@ -0,0 +1,186 @@
+// runs whole scripts through the shucks binary and checks what comes out
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs};
+
+static SCRIPTS: AtomicUsize = AtomicUsize::new(0);
+
+struct Output {
+    stdout: String,
+    stderr: String,
+    status: i32,
+}
+
+fn run(source: &str) -> Output {
+    let n = SCRIPTS.fetch_add(1, Ordering::Relaxed);
+    let dir = env::temp_dir().join(format!("shucks-test-{}-{n}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("script.shk");
+    fs::write(&script, source).unwrap();
+    // a script that hangs fails with timeout's status 124 instead of stalling the tests
+    let output = Command::new("timeout")
+        .arg("10")
+        .arg(env!("CARGO_BIN_EXE_shucks"))
+        .arg(&script)
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    Output {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        status: output.status.code().unwrap_or(-1),
+    }
+}
+
+fn stdout(source: &str) -> String {
+    let output = run(source);
+    assert_eq!(output.status, 0, "stderr: {}", output.stderr);
+    output.stdout
+}
+
+#[test]
+fn pipelines() {
+    assert_eq!(stdout("echo hello | tr a-z A-Z\n"), "HELLO\n");
+    assert_eq!(stdout("seq 3 | sort -r | head -1\n"), "3\n");
+}
+
+#[test]
+fn in_process_stages_stream() {
+    assert_eq!(
+        stdout("while true { echo y } | head -1\necho done\n"),
+        "y\ndone\n"
+    );
+    let source = "function gen() {\n    for i in 1..=3 { echo line $i }\n}\ngen | tr a-z A-Z\n";
+    assert_eq!(stdout(source), "LINE 1\nLINE 2\nLINE 3\n");
+}
+
+#[test]
+fn number_arguments() {
+    assert_eq!(
+        stdout("echo 1.10 007 0o755 1_000 1e3 0x1F\n"),
+        "1.10 007 0o755 1_000 1e3 0x1F\n"
+    );
+    assert_eq!(
+        stdout("echo 18446744073709551615\n"),
+        "18446744073709551615\n"
+    );
+    assert_eq!(stdout("echo 2 * 3\n"), "6\n");
+}
+
+#[test]
+fn redirects() {
+    assert_eq!(
+        stdout("echo one -> f.txt\necho two >> f.txt\ncat f.txt\n"),
+        "one\ntwo\n"
+    );
+    assert_eq!(stdout("ls /nonexistent 2->&1 | wc -l\n").trim(), "1");
+}
+
+#[test]
+fn redirects_above_nine() {
+    let command = "bash -c \"echo eleven >&11; echo ten >&10\" 11-> a 10-> b\n";
+    assert_eq!(stdout(&format!("{command}cat a\n")), "eleven\n");
+    assert_eq!(stdout(&format!("{command}cat b\n")), "ten\n");
+}
+
+#[test]
+fn subshells_are_isolated() {
+    let source = "x = 1\n( x = 2\n  echo inner $x )\necho outer $x\n";
+    assert_eq!(stdout(source), "inner 2\nouter 1\n");
+    assert_eq!(stdout("( exit 3 ) || echo $?\n"), "3\n");
+}
+
+#[test]
+fn here_docs() {
+    let source = "name = \"world\"\ncat <<EOF\nhello $name\nEOF\ncat <<'EOF'\nliteral $name\nEOF\n";
+    assert_eq!(stdout(source), "hello world\nliteral $name\n");
+    assert_eq!(stdout("cat <<< \"one\"\n"), "one\n");
+}
+
+#[test]
+fn process_substitution() {
+    assert_eq!(stdout("cat <(echo one) <(echo two)\n"), "one\ntwo\n");
+}
+
+#[test]
+fn syntax_errors_have_lines() {
+    let output = run("echo hi\nf = 3 4\n");
+    assert_eq!(output.status, 2);
+    assert!(output.stderr.contains(":2:"), "stderr: {}", output.stderr);
+}
+
+#[test]
+fn replace_refers_to_groups_by_number() {
+    let source = "x = replace(\"2024-10\", \"(\\d+)-(\\d+)\", \"${2}/$1\")\necho $x\n";
+    assert_eq!(stdout(source), "10/2024\n");
+}
+
+#[test]
+fn index_assignment() {
+    let source =
+        "xs = [1, 2, 3]\nxs[0] = 5\nxs[-1] += 10\nm = {b: [1, 2]}\nm[\"b\"][1] = 9\necho $xs $m\n";
+    assert_eq!(stdout(source), "[5, 2, 13] {b: [1, 9]}\n");
+}
+
+#[test]
+fn error_status_out_of_range() {
+    let output = run("throw error(\"boom\", \"custom\", 4294967297)\n");
+    assert_eq!(output.status, 1);
+    assert!(
+        output.stderr.contains("out of range"),
+        "stderr: {}",
+        output.stderr
+    );
+    assert_eq!(run("throw error(\"boom\", \"custom\", 7)\n").status, 7);
+}
+
+#[test]
+fn indexes_and_glob_classes_in_arguments() {
+    let source = "xs = [1, 2, 3]\ni = 1\necho xs[i] xs[0..2]\n";
+    assert_eq!(stdout(source), "2 1 2\n");
+    let source = "touch a1 b2 c3\nls [ab]*\n";
+    assert_eq!(stdout(source), "a1\nb2\n");
+}
+
+#[test]
+fn wait_returns_when_a_job_stops() {
+    // the job stops itself, with a timer that continues it once the script is done with it
+    let job = "set -- $(ps -o ppid= -p $$); (sleep 0.5; kill -CONT $1) & kill -STOP $1";
+    let output = run(&format!("sh -c \"{job}\" &\nwait %1\necho status $?\n"));
+    assert_eq!(output.stdout, "status 148\n");
+    assert!(
+        output.stderr.contains("is stopped"),
+        "stderr: {}",
+        output.stderr
+    );
+}
+
+#[test]
+fn tilde_user_homes() {
+    let output = stdout("echo ~root ~no_such_user_here/x\n");
+    let words: Vec<_> = output.split_whitespace().collect();
+    assert!(words[0].starts_with('/'), "{output}");
+    assert_eq!(words[1], "~no_such_user_here/x");
+}
+
+#[test]
+fn exit_traps_run() {
+    let source = "name = \"cleanup\"\ntrap { echo $name } EXIT\necho body\n";
+    assert_eq!(stdout(source), "body\ncleanup\n");
+    let output = run("trap { echo cleanup } EXIT\nthrow error(\"boom\")\n");
+    assert_eq!((output.stdout.as_str(), output.status), ("cleanup\n", 1));
+}
+
+#[test]
+fn aliases_are_lexed_when_defined() {
+    let output = run("alias g = \"grep ^x\"\necho $?\nalias ll = \"echo long\"\nll\n");
+    assert_eq!(output.stdout, "1\nlong\n");
+    assert!(
+        output.stderr.contains("alias: g: unexpected character"),
+        "stderr: {}",
+        output.stderr
+    );
+    assert_eq!(stdout("alias ll = \"echo long\"\nunalias ll\nalias\n"), "");
+}